@@ -16,7 +16,7 @@ impl TextDisplay {
             max_y: 0,
         }
     }
-    pub fn init(&mut self, hw: &mut Hw) {
+    pub fn init(&mut self, hw: &mut impl Hw) {
         let font = include_bytes!("VGA-ROM.F14");
         for (addr, &data) in font.iter().enumerate() {
             hw.set_reg(
@@ -33,7 +33,7 @@ impl TextDisplay {
         hw.set_reg(R_TEXT_TRANSPARENT, 15);
         hw.set_reg(R_TEXT_EN, B_TEXT_EN);
     }
-    pub fn clear(&mut self, hw: &mut Hw, x: u16, y: u16, w: u16, h: u16) {
+    pub fn clear(&mut self, hw: &mut impl Hw, x: u16, y: u16, w: u16, h: u16) {
         for j in y..y + h {
             for i in x..x + w {
                 hw.set_reg(
@@ -43,7 +43,7 @@ impl TextDisplay {
             }
         }
     }
-    pub fn clear_all(&mut self, hw: &mut Hw) {
+    pub fn clear_all(&mut self, hw: &mut impl Hw) {
         if self.min_x < self.max_x && self.min_y < self.max_y {
             self.clear(
                 hw,
@@ -58,7 +58,7 @@ impl TextDisplay {
             self.max_y = 0;
         }
     }
-    pub fn print(&mut self, hw: &mut Hw, mut x: u16, mut y: u16, str: &str) {
+    pub fn print(&mut self, hw: &mut impl Hw, mut x: u16, mut y: u16, str: &str) {
         let x_left = x;
         assert!(x < 80 && y < 30);
         for ch in str.chars() {
@@ -88,7 +88,7 @@ impl TextDisplay {
     }
 }
 
-const STATS_ROWS: [&'static str; 7] = [
+pub const STATS_ROWS: [&'static str; 7] = [
     "starter->",
     "coarse->",
     "fine->",
@@ -108,21 +108,21 @@ impl StatsCollection {
             stats: Vec::with_capacity(STATS_ROWS.len() * 4),
         }
     }
-    pub fn reset(&mut self, hw: &mut Hw) {
+    pub fn reset(&mut self, hw: &mut impl Hw) {
         self.stats.clear();
         self.stats
-            .extend((0..7 * 4).map(|i| hw.get_reg(0x800 + 4 * i)));
+            .extend((0..7 * 4).map(|i| hw.get_reg(R_STATS + 4 * i)));
     }
-    pub fn start(&mut self, hw: &mut Hw) {
+    pub fn start(&mut self, hw: &mut impl Hw) {
         hw.set_reg(R_STATS_ENABLED, 1);
     }
-    pub fn stop(&mut self, hw: &mut Hw) {
+    pub fn stop(&mut self, hw: &mut impl Hw) {
         hw.set_reg(R_STATS_ENABLED, 0);
     }
-    pub fn print(&mut self, hw: &mut Hw) {
+    pub fn print(&mut self, hw: &mut impl Hw) {
         let stats = (0..7 * 4)
             .map(|i| {
-                hw.get_reg(0x800 + 4 * i)
+                hw.get_reg(R_STATS + 4 * i)
                     .wrapping_sub(self.stats[i as usize])
             })
             .collect::<Vec<_>>();
@@ -0,0 +1,314 @@
+// Software version of the hardware behind Hw, so that the driver can run on a PC. It implements
+// the register map of hw/ConfigDefs.bsv on top of a plain memory buffer and rasterizes with
+// render::fixed, which matches the hardware bit for bit.
+//
+// Everything happens synchronously inside set_reg: a clear or a draw has completed by the time
+// the write returns, and the video output is always in a vsync, so nothing is ever waited for.
+
+use std::borrow::Cow;
+
+use rs_common::{
+    mesh::Color,
+    render::{
+        DepthMode, SamplerState, TILE_SIZE, Texture, TextureType, WrapMode,
+        fixed::{self, HwTriangle},
+    },
+};
+
+use crate::hw::*;
+
+const TRIANGLE_SIZE: u32 = size_of::<HwTriangle>() as u32;
+// the text overlay's memories, indexed by 12 bits
+const TEXT_LEN: usize = 4096;
+const TEXT_COLORS: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA, 0x555555,
+    0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+// the pipeline stages the counters at R_STATS sit behind, see Stats.bsv
+#[derive(Clone, Copy)]
+enum Stage {
+    Starter,
+    Coarse,
+    Fine,
+    Depth,
+    Pixel,
+    Uv,
+    Texture,
+}
+
+pub struct Emulator {
+    // values of the plain read/write registers, indexed by address / 4
+    regs: Vec<u32>,
+    // u64 so that Hw::read and Hw::write get the alignment they would have on the hardware
+    mem: Vec<u64>,
+    text: Vec<u16>,
+    font: Vec<u8>,
+    flushed: bool,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        let mut regs = vec![0; REG_LEN as usize / 4];
+        // reset values from the mkCBRegRW instances
+        for (addr, value) in [
+            (R_DISPLAY_FB, 0x1000_0000),
+            (R_RENDER_TARGET, 0x1000_0000),
+            (R_DEPTH_BUFFER, 0x1080_0000),
+            (R_DEPTH_MODE, 4 | B_DEPTH_WRITE),
+            (R_CLEAR_ADDR, 0x1000_0000),
+            (R_CMD_ADDR, 0x1020_0000),
+        ] {
+            regs[addr as usize / 4] = value;
+        }
+        Emulator {
+            regs,
+            mem: vec![0; MEM_LEN as usize / 8],
+            text: vec![0; TEXT_LEN],
+            font: vec![0; TEXT_LEN],
+            flushed: false,
+        }
+    }
+    fn reg(&self, addr: u32) -> u32 {
+        self.regs[addr as usize / 4]
+    }
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mem.as_ptr() as *const u8, MEM_LEN as usize) }
+    }
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.mem.as_mut_ptr() as *mut u8, MEM_LEN as usize)
+        }
+    }
+    // like a DMA, addresses outside of vram are dropped
+    fn mem_range(addr: u32, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = addr.checked_sub(MEM_START)? as usize;
+        (start + len <= MEM_LEN as usize).then_some(start..start + len)
+    }
+    fn count(&mut self, stage: Stage, n: u32) {
+        // every transfer counts as a cycle with data on the left and room on the right
+        if self.reg(R_STATS_ENABLED) & 1 != 0 {
+            let reg = &mut self.regs[(R_STATS + 16 * stage as u32 + 12) as usize / 4];
+            *reg = reg.wrapping_add(n);
+        }
+    }
+    // Clear: height rows of width 16 byte words, stride words apart
+    fn run_clear(&mut self) {
+        let mut addr = self.reg(R_CLEAR_ADDR);
+        let stride = self.reg(R_CLEAR_STRIDE) & 0xffff;
+        let [width, height] = [0, 16].map(|shift| self.reg(R_CLEAR_WIDTH_HEIGHT) >> shift & 0xffff);
+        let data = self.reg(R_CLEAR_DATA).to_le_bytes();
+        for _ in 0..height {
+            if let Some(range) = Self::mem_range(addr, width as usize * 16) {
+                for chunk in self.bytes_mut()[range].chunks_exact_mut(4) {
+                    chunk.copy_from_slice(&data);
+                }
+            }
+            addr = addr.wrapping_add(stride * 16);
+        }
+    }
+    fn texture_type(&self) -> TextureType {
+        let en = self.reg(R_TEXTURE_EN);
+        let size = |shift: u32| 8 << (en >> shift & 7);
+        TextureType {
+            width: size(4),
+            height: size(8),
+            stride: size(12),
+        }
+    }
+    fn sampler(&self) -> SamplerState {
+        let [r, g, b, a] = self.reg(R_TEXTURE_BORDER).to_le_bytes();
+        SamplerState {
+            wrap: match self.reg(R_TEXTURE_EN) >> 1 & 3 {
+                0 => WrapMode::Wrap,
+                1 => WrapMode::ClampToEdge,
+                _ => WrapMode::ClampToBorder,
+            },
+            border: Color { r, g, b, a },
+        }
+    }
+    fn depth_mode(&self) -> DepthMode {
+        match self.reg(R_DEPTH_MODE) & 7 {
+            0 => DepthMode::Always,
+            1 => DepthMode::Never,
+            2 => DepthMode::Lt,
+            3 => DepthMode::Le,
+            4 => DepthMode::Gt,
+            5 => DepthMode::Ge,
+            6 => DepthMode::Eq,
+            _ => DepthMode::Ne,
+        }
+    }
+    // DepthTest: each 4x4 tile keeps its depths in 32 bytes, ordered like the cache tags
+    fn depth_addr(&self, x: usize, y: usize) -> u32 {
+        let (tx, ty) = (x / TILE_SIZE, y / TILE_SIZE);
+        let tag = (ty >> 4 & 31) << 5 | tx >> 4 & 31;
+        let cache_addr = (ty & 15) << 4 | tx & 15;
+        let i = (y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE;
+        self.reg(R_DEPTH_BUFFER)
+            .wrapping_add(((tag * 256 + cache_addr) * 32 + i * 2) as u32)
+    }
+    // Starter through PixelOut for the len triangles at R_CMD_ADDR
+    fn run_draw(&mut self, len: u32) {
+        let texture = (self.reg(R_TEXTURE_EN) & B_TEXTURE_EN != 0).then(|| {
+            let ty = self.texture_type();
+            // the whole texture is copied, as the pixels are written to the same memory
+            let range = Self::mem_range(self.reg(R_TEXTURE_ADDR), ty.stride * ty.height * 4)
+                .expect("texture outside of vram");
+            Texture {
+                data: Cow::Owned(self.bytes()[range].to_vec()),
+                ty,
+            }
+        });
+        let sampler = self.sampler();
+        let depth_mode = self.depth_mode();
+        let depth_write = self.reg(R_DEPTH_MODE) & B_DEPTH_WRITE != 0;
+        let fb = self.reg(R_RENDER_TARGET);
+        let cmd_addr = self.reg(R_CMD_ADDR);
+        for i in 0..len {
+            let t: HwTriangle = unsafe { self.read(cmd_addr + i * TRIANGLE_SIZE) };
+            self.count(Stage::Starter, 1);
+            // the bbox fields are UInt #(9) tile coordinates
+            let [min_x, min_y, max_x, max_y] =
+                [t.min_x, t.min_y, t.max_x, t.max_y].map(|c| (c & 0x1ff) as usize);
+            for ty in 0..=max_y.saturating_sub(min_y) {
+                for tx in 0..=max_x.saturating_sub(min_x) {
+                    if !t.tile_viable(tx, ty) {
+                        continue;
+                    }
+                    self.count(Stage::Coarse, 1);
+                    let mut inside = false;
+                    let mut passed = false;
+                    for oy in 0..TILE_SIZE {
+                        for ox in 0..TILE_SIZE {
+                            let (px, py) = (tx * TILE_SIZE + ox, ty * TILE_SIZE + oy);
+                            let e = t.edge_fns(px, py);
+                            if e.iter().any(|&e| e < 0) {
+                                continue;
+                            }
+                            inside = true;
+                            let (x, y) = (min_x * TILE_SIZE + px, min_y * TILE_SIZE + py);
+                            let z = fixed::depth(e);
+                            let Some(depth) = Self::mem_range(self.depth_addr(x, y), 2) else {
+                                continue;
+                            };
+                            let buffer =
+                                i16::from_le_bytes([0, 1].map(|i| self.bytes()[depth.start + i]));
+                            if !depth_mode.test(z, buffer) {
+                                continue;
+                            }
+                            passed = true;
+                            if depth_write {
+                                self.bytes_mut()[depth].copy_from_slice(&z.to_le_bytes());
+                            }
+                            self.count(Stage::Pixel, 1);
+                            self.count(Stage::Uv, 1);
+                            self.count(Stage::Texture, 1);
+                            let (uv, rgb) = t.interpolate(e);
+                            let rgb = fixed::shade(uv, rgb, texture.as_ref(), sampler);
+                            let addr = fb.wrapping_add((y * 640 * 4 + x * 4) as u32);
+                            if let Some(range) = Self::mem_range(addr, 4) {
+                                self.bytes_mut()[range]
+                                    .copy_from_slice(&[rgb[0], rgb[1], rgb[2], 0]);
+                            }
+                        }
+                    }
+                    self.count(Stage::Fine, inside as u32);
+                    self.count(Stage::Depth, passed as u32);
+                }
+            }
+        }
+    }
+    // TextOverlay: the color covering pixel (x, y), if any
+    fn text_pixel(&self, x: usize, y: usize) -> Option<u32> {
+        if self.reg(R_TEXT_EN) & B_TEXT_EN == 0 {
+            return None;
+        }
+        let ch = self.text[(80 * (y >> 4) + x / 8) % TEXT_LEN];
+        let row = self.font[(14 * (ch as usize & 0xff) + (y & 15)) % TEXT_LEN];
+        let is_fg = y & 15 < 14 && row >> (7 - x % 8) & 1 != 0;
+        let color = if is_fg { ch >> 8 & 15 } else { ch >> 12 };
+        (color as u32 != self.reg(R_TEXT_TRANSPARENT) & 15).then(|| TEXT_COLORS[color as usize])
+    }
+    // what the video output currently shows, 640x480 pixels of 0xRRGGBB
+    pub fn scanout(&self) -> Vec<u32> {
+        let fb = self.reg(R_DISPLAY_FB);
+        let mut pixels = Vec::with_capacity(640 * 480);
+        for y in 0..480 {
+            for x in 0..640 {
+                let color = self.text_pixel(x, y).unwrap_or_else(|| {
+                    match Self::mem_range(fb.wrapping_add((y * 640 + x) as u32 * 4), 4) {
+                        Some(range) => {
+                            let [r, g, b, _] = [0, 1, 2, 3].map(|i| self.bytes()[range.start + i]);
+                            (r as u32) << 16 | (g as u32) << 8 | b as u32
+                        }
+                        // the DMA gets an error response, the video shows blue
+                        None => 0xFF,
+                    }
+                });
+                pixels.push(color);
+            }
+        }
+        pixels
+    }
+}
+
+impl Hw for Emulator {
+    fn get_reg(&mut self, addr: u32) -> u32 {
+        assert!(addr < REG_LEN && (addr & 3) == 0);
+        match addr {
+            R_STATUS => {
+                let flushed = if self.flushed { B_STATUS_FLUSHED } else { 0 };
+                B_STATUS_SEEN_VSYNC | B_STATUS_IN_VSYNC | flushed
+            }
+            _ => self.reg(addr),
+        }
+    }
+    fn set_reg(&mut self, addr: u32, value: u32) {
+        assert!(addr < REG_LEN && (addr & 3) == 0);
+        match addr {
+            // the bits are cleared by writing 1, seen_vsync is set again right away
+            R_STATUS => {
+                if value & B_STATUS_FLUSHED != 0 {
+                    self.flushed = false;
+                }
+            }
+            R_CONTROL => {
+                // the command bits clear themselves once taken
+                self.regs[addr as usize / 4] = value & !0xf;
+                if value & B_CONTROL_CLEAR != 0 {
+                    self.run_clear();
+                }
+                if value & B_CONTROL_START != 0 {
+                    self.run_draw(value >> 16);
+                }
+                if value & B_CONTROL_FLUSH != 0 {
+                    self.flushed = true;
+                }
+            }
+            R_TEXT_ACCESS => {
+                let index = (value >> 16 & 0xfff) as usize;
+                if value >> 31 != 0 {
+                    if value & B_TEXT_ACCESS_FONT == B_TEXT_ACCESS_FONT {
+                        self.font[index] = value as u8;
+                    } else {
+                        self.text[index] = value as u16;
+                    }
+                }
+                self.regs[addr as usize / 4] = value & !(1 << 31);
+            }
+            // the stats counters are read only
+            _ if addr >= R_STATS => {}
+            _ => self.regs[addr as usize / 4] = value,
+        }
+    }
+    fn mem(&mut self) -> &mut [u8] {
+        self.bytes_mut()
+    }
+}
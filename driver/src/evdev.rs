@@ -1,23 +1,238 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    collections::HashMap,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use evdev::{Device, EventSummary, KeyCode, RelativeAxisCode};
+use evdev::{AbsoluteAxisCode, Device, EventSummary, KeyCode, RelativeAxisCode};
 use rs_common::input::{self, *};
 
+// how often the readers check whether they should stop
+const STOP_POLL_MS: libc::c_int = 100;
+// how often /dev/input is checked for devices that were plugged in
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// selects input devices by a substring of their name, or with cap:keyboard, cap:mouse and
+// cap:gamepad by what they can do.
+#[derive(Debug, Clone)]
+pub enum DeviceFilter {
+    Name(String),
+    Keyboard,
+    Mouse,
+    Gamepad,
+}
+
+impl FromStr for DeviceFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("cap:") {
+            Some("keyboard") => Ok(DeviceFilter::Keyboard),
+            Some("mouse") => Ok(DeviceFilter::Mouse),
+            Some("gamepad") => Ok(DeviceFilter::Gamepad),
+            Some(cap) => Err(format!(
+                "unknown capability {cap}, use keyboard, mouse or gamepad"
+            )),
+            None => Ok(DeviceFilter::Name(s.to_string())),
+        }
+    }
+}
+
+impl DeviceFilter {
+    fn matches(&self, dev: &Device) -> bool {
+        match self {
+            DeviceFilter::Name(name) => dev.name().is_some_and(|n| n.contains(name.as_str())),
+            // not just any key, power buttons and the like have those too
+            DeviceFilter::Keyboard => dev
+                .supported_keys()
+                .is_some_and(|keys| keys.contains(KeyCode::KEY_A)),
+            DeviceFilter::Mouse => dev
+                .supported_relative_axes()
+                .is_some_and(|axes| axes.contains(RelativeAxisCode::REL_X)),
+            DeviceFilter::Gamepad => is_gamepad(dev),
+        }
+    }
+}
+
+// joysticks and touchpads have absolute axes too, but no face buttons
+fn is_gamepad(dev: &Device) -> bool {
+    dev.supported_keys()
+        .is_some_and(|keys| keys.contains(KeyCode::BTN_SOUTH))
+}
+
+#[derive(Debug, Clone)]
+pub struct EvdevConfig {
+    // a device is used if it matches any of these and none of exclude
+    pub include: Vec<DeviceFilter>,
+    pub exclude: Vec<DeviceFilter>,
+    // take the devices away from the console and other readers
+    pub grab: bool,
+}
+
+impl Default for EvdevConfig {
+    fn default() -> Self {
+        EvdevConfig {
+            include: vec![
+                DeviceFilter::Keyboard,
+                DeviceFilter::Mouse,
+                DeviceFilter::Gamepad,
+            ],
+            exclude: Vec::new(),
+            grab: true,
+        }
+    }
+}
+
+impl EvdevConfig {
+    fn accepts(&self, dev: &Device) -> bool {
+        self.include.iter().any(|f| f.matches(dev)) && !self.exclude.iter().any(|f| f.matches(dev))
+    }
+}
+
 pub struct EvdevSource {
     receiver: Receiver<InputEvent>,
+    stop: Arc<AtomicBool>,
+    scanner: Option<JoinHandle<()>>,
 }
 
 impl EvdevSource {
-    pub fn new() -> Self {
+    pub fn new(config: EvdevConfig) -> Self {
         let (sender, receiver) = mpsc::channel();
-        for (dev_path, dev) in evdev::enumerate() {
-            let reader = EvdevReader {
-                dev_path,
-                sender: sender.clone(),
+        let stop = Arc::new(AtomicBool::new(false));
+        let scanner = Scanner {
+            config,
+            sender,
+            stop: stop.clone(),
+            dir: PathBuf::from("/dev/input"),
+            devices: HashMap::new(),
+        };
+        EvdevSource {
+            receiver,
+            stop,
+            scanner: Some(std::thread::spawn(move || scanner.run())),
+        }
+    }
+}
+
+// stops the scanner and the readers, which releases the grabbed devices
+impl Drop for EvdevSource {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(scanner) = self.scanner.take() {
+            let _ = scanner.join();
+        }
+    }
+}
+
+// what became of a device node the scanner has seen
+enum Node {
+    Reader(JoinHandle<()>),
+    // not accepted by the EvdevConfig
+    Rejected,
+    // tried again on the next scan, it may not be readable yet right after being plugged in
+    OpenFailed,
+}
+
+// watches /dev/input and starts a reader for each accepted device
+struct Scanner {
+    config: EvdevConfig,
+    sender: Sender<InputEvent>,
+    stop: Arc<AtomicBool>,
+    // /dev/input, other than in tests
+    dir: PathBuf,
+    devices: HashMap<PathBuf, Node>,
+}
+
+impl Scanner {
+    fn scan(&mut self) -> std::io::Result<()> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            // there are no input devices at all
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with("event") {
+                paths.push(path);
+            }
+        }
+        // a node that is gone was unplugged, its reader has stopped or is about to. A reader
+        // that has stopped on its own lost its device, which may have come back under the
+        // same node before this scan.
+        self.devices.retain(|path, node| {
+            paths.contains(path) && !matches!(node, Node::Reader(reader) if reader.is_finished())
+        });
+        for path in paths {
+            let node = match self.devices.get(&path) {
+                None => self.open(&path, true),
+                // the error was reported the first time
+                Some(Node::OpenFailed) => self.open(&path, false),
+                Some(_) => continue,
             };
-            std::thread::spawn(move || reader.run(dev));
+            self.devices.insert(path, node);
+        }
+        Ok(())
+    }
+    fn open(&self, path: &Path, report_error: bool) -> Node {
+        let dev = match Device::open(path) {
+            Ok(dev) => dev,
+            Err(err) => {
+                if report_error {
+                    eprintln!("input device error: {path:?}: {err}");
+                }
+                return Node::OpenFailed;
+            }
+        };
+        if !self.config.accepts(&dev) {
+            return Node::Rejected;
+        }
+        eprintln!("input device {path:?}: {}", dev.name().unwrap_or("unnamed"));
+        let mut axis_ranges = HashMap::new();
+        if is_gamepad(&dev) {
+            match dev.get_absinfo() {
+                Ok(infos) => {
+                    for (code, info) in infos {
+                        axis_ranges.insert(code, (info.minimum(), info.maximum()));
+                    }
+                }
+                Err(err) => eprintln!("input device error: {path:?}: {err}"),
+            }
+        }
+        let reader = EvdevReader {
+            dev_path: path.to_path_buf(),
+            sender: self.sender.clone(),
+            stop: self.stop.clone(),
+            grab: self.config.grab,
+            axis_ranges,
+            hat: [0; 2],
+        };
+        Node::Reader(std::thread::spawn(move || reader.run(dev)))
+    }
+    fn run(mut self) {
+        while !self.stop.load(Ordering::Relaxed) {
+            if let Err(err) = self.scan() {
+                eprintln!("input device error: {:?}: {err}", self.dir);
+            }
+            let next = Instant::now() + RESCAN_INTERVAL;
+            while Instant::now() < next && !self.stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(STOP_POLL_MS as u64));
+            }
+        }
+        for node in self.devices.into_values() {
+            if let Node::Reader(reader) = node {
+                let _ = reader.join();
+            }
         }
-        EvdevSource { receiver }
     }
 }
 
@@ -28,8 +243,14 @@ impl input::InputSource for EvdevSource {
 }
 
 struct EvdevReader {
-    dev_path: std::path::PathBuf,
+    dev_path: PathBuf,
     sender: Sender<InputEvent>,
+    stop: Arc<AtomicBool>,
+    grab: bool,
+    // minimum and maximum of the gamepad axes, empty for other devices
+    axis_ranges: HashMap<AbsoluteAxisCode, (i32, i32)>,
+    // the dpad is reported as a hat axis by many gamepads, this is its last x and y
+    hat: [i32; 2],
 }
 
 #[derive(Debug)]
@@ -58,28 +279,106 @@ impl EvdevReader {
                 (EvdevKey::Key(key), 1) => self.sender.send(InputEvent::KeyDown(key))?,
                 (EvdevKey::MouseButton(btn), 0) => self.sender.send(InputEvent::MouseButtonUp(btn))?,
                 (EvdevKey::MouseButton(btn), 1) => self.sender.send(InputEvent::MouseButtonDown(btn))?,
+                (EvdevKey::GamepadButton(btn), 0) => {
+                    self.sender.send(InputEvent::GamepadButtonUp(btn))?
+                }
+                (EvdevKey::GamepadButton(btn), 1) => {
+                    self.sender.send(InputEvent::GamepadButtonDown(btn))?
+                }
                 _ => {}
             },
             EventSummary::RelativeAxis(_, code, value) => match code {
                 RelativeAxisCode::REL_X => self.sender.send(InputEvent::RelMouse(value, 0))?,
                 RelativeAxisCode::REL_Y => self.sender.send(InputEvent::RelMouse(0, value))?,
+                RelativeAxisCode::REL_WHEEL => self.sender.send(InputEvent::Scroll(0, value))?,
+                RelativeAxisCode::REL_HWHEEL => self.sender.send(InputEvent::Scroll(value, 0))?,
                 _ => {}
             },
+            EventSummary::AbsoluteAxis(_, code, value) => {
+                let Some(&(min, max)) = self.axis_ranges.get(&code) else {
+                    return Ok(());
+                };
+                match code {
+                    AbsoluteAxisCode::ABS_HAT0X => self.hat(0, value)?,
+                    AbsoluteAxisCode::ABS_HAT0Y => self.hat(1, value)?,
+                    _ => {
+                        if let Some(axis) = map_axis(code)
+                            && max > min
+                        {
+                            let t = (value - min) as f64 / (max - min) as f64;
+                            let value = match axis {
+                                GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => t,
+                                _ => t * 2.0 - 1.0,
+                            };
+                            self.sender.send(InputEvent::GamepadAxis(axis, value))?
+                        }
+                    }
+                }
+            }
             _ => {}
         }
         Ok(())
     }
-    fn run_err(&mut self, mut dev: Device) -> Result<(), EvdevError> {
-        dev.grab()?;
-        loop {
-            for event in dev.fetch_events()? {
-                self.process(event)?;
+    // turns a hat axis going from -1 to 1 into presses and releases of the dpad buttons
+    fn hat(&mut self, i: usize, value: i32) -> Result<(), mpsc::SendError<InputEvent>> {
+        let buttons = [
+            [GamepadButton::DpadLeft, GamepadButton::DpadRight],
+            [GamepadButton::DpadUp, GamepadButton::DpadDown],
+        ][i];
+        let button = |value: i32| match value.signum() {
+            -1 => Some(buttons[0]),
+            1 => Some(buttons[1]),
+            _ => None,
+        };
+        let previous = std::mem::replace(&mut self.hat[i], value.signum());
+        if previous == value.signum() {
+            return Ok(());
+        }
+        if let Some(btn) = button(previous) {
+            self.sender.send(InputEvent::GamepadButtonUp(btn))?;
+        }
+        if let Some(btn) = button(value) {
+            self.sender.send(InputEvent::GamepadButtonDown(btn))?;
+        }
+        Ok(())
+    }
+    fn run_err(&mut self, dev: &mut Device) -> Result<(), EvdevError> {
+        if self.grab {
+            dev.grab()?;
+        }
+        // reads don't block, so that the stop flag is checked at least every STOP_POLL_MS
+        dev.set_nonblocking(true)?;
+        while !self.stop.load(Ordering::Relaxed) {
+            let mut fd = libc::pollfd {
+                fd: dev.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fd, 1, STOP_POLL_MS) } <= 0 {
+                continue;
+            }
+            match dev.fetch_events() {
+                Ok(events) => {
+                    for event in events {
+                        self.process(event)?;
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err.into()),
             }
         }
+        Ok(())
     }
-    fn run(mut self, dev: Device) {
-        if let Err(err) = self.run_err(dev) {
-            eprintln!("input device error: {:?}: {:?}", self.dev_path, err);
+    fn run(mut self, mut dev: Device) {
+        match self.run_err(&mut dev) {
+            Err(EvdevError::IoError(err)) if err.raw_os_error() == Some(libc::ENODEV) => {
+                eprintln!("input device {:?} removed", self.dev_path);
+            }
+            Err(err) => eprintln!("input device error: {:?}: {:?}", self.dev_path, err),
+            Ok(()) => {}
+        }
+        if self.grab {
+            let _ = dev.ungrab();
         }
     }
 }
@@ -88,6 +387,19 @@ pub enum EvdevKey {
     Unknown,
     Key(Key),
     MouseButton(MouseButton),
+    GamepadButton(GamepadButton),
+}
+
+fn map_axis(code: AbsoluteAxisCode) -> Option<GamepadAxis> {
+    match code {
+        AbsoluteAxisCode::ABS_X => Some(GamepadAxis::LeftX),
+        AbsoluteAxisCode::ABS_Y => Some(GamepadAxis::LeftY),
+        AbsoluteAxisCode::ABS_RX => Some(GamepadAxis::RightX),
+        AbsoluteAxisCode::ABS_RY => Some(GamepadAxis::RightY),
+        AbsoluteAxisCode::ABS_Z => Some(GamepadAxis::LeftTrigger),
+        AbsoluteAxisCode::ABS_RZ => Some(GamepadAxis::RightTrigger),
+        _ => None,
+    }
 }
 
 fn map_key(code: KeyCode) -> EvdevKey {
@@ -184,6 +496,86 @@ fn map_key(code: KeyCode) -> EvdevKey {
         KeyCode::BTN_LEFT => EvdevKey::MouseButton(MouseButton::Left),
         KeyCode::BTN_MIDDLE => EvdevKey::MouseButton(MouseButton::Middle),
         KeyCode::BTN_RIGHT => EvdevKey::MouseButton(MouseButton::Right),
+        KeyCode::BTN_SOUTH => EvdevKey::GamepadButton(GamepadButton::South),
+        KeyCode::BTN_EAST => EvdevKey::GamepadButton(GamepadButton::East),
+        KeyCode::BTN_NORTH => EvdevKey::GamepadButton(GamepadButton::North),
+        KeyCode::BTN_WEST => EvdevKey::GamepadButton(GamepadButton::West),
+        KeyCode::BTN_TL => EvdevKey::GamepadButton(GamepadButton::LeftShoulder),
+        KeyCode::BTN_TR => EvdevKey::GamepadButton(GamepadButton::RightShoulder),
+        KeyCode::BTN_THUMBL => EvdevKey::GamepadButton(GamepadButton::LeftStick),
+        KeyCode::BTN_THUMBR => EvdevKey::GamepadButton(GamepadButton::RightStick),
+        KeyCode::BTN_SELECT => EvdevKey::GamepadButton(GamepadButton::Select),
+        KeyCode::BTN_START => EvdevKey::GamepadButton(GamepadButton::Start),
+        KeyCode::BTN_MODE => EvdevKey::GamepadButton(GamepadButton::Mode),
+        KeyCode::BTN_DPAD_UP => EvdevKey::GamepadButton(GamepadButton::DpadUp),
+        KeyCode::BTN_DPAD_DOWN => EvdevKey::GamepadButton(GamepadButton::DpadDown),
+        KeyCode::BTN_DPAD_LEFT => EvdevKey::GamepadButton(GamepadButton::DpadLeft),
+        KeyCode::BTN_DPAD_RIGHT => EvdevKey::GamepadButton(GamepadButton::DpadRight),
         _ => EvdevKey::Unknown,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished_reader() -> Node {
+        let reader = std::thread::spawn(|| {});
+        while !reader.is_finished() {
+            std::thread::yield_now();
+        }
+        Node::Reader(reader)
+    }
+
+    #[test]
+    fn scan_reopens_lost_devices() {
+        let dir = std::env::temp_dir().join(format!("evdev_scan_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // regular files, which can't be opened as devices
+        for name in ["event0", "event1", "event2", "event3", "mouse0"] {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+        let (done_sender, done) = mpsc::channel::<()>();
+        let running = std::thread::spawn(move || {
+            let _ = done.recv();
+        });
+        let mut scanner = Scanner {
+            config: EvdevConfig::default(),
+            sender: mpsc::channel().0,
+            stop: Arc::default(),
+            dir: dir.clone(),
+            devices: HashMap::from([
+                (dir.join("event1"), finished_reader()),
+                (dir.join("event2"), Node::Rejected),
+                (dir.join("event3"), Node::Reader(running)),
+                (dir.join("event4"), Node::Rejected),
+            ]),
+        };
+        scanner.scan().unwrap();
+        let node = |scanner: &Scanner, name: &str| match scanner.devices.get(&dir.join(name)) {
+            None => "none",
+            Some(Node::Reader(_)) => "reader",
+            Some(Node::Rejected) => "rejected",
+            Some(Node::OpenFailed) => "open failed",
+        };
+        // the stopped reader is replaced by another try to open the node
+        assert_eq!(node(&scanner, "event0"), "open failed");
+        assert_eq!(node(&scanner, "event1"), "open failed");
+        assert_eq!(node(&scanner, "event2"), "rejected");
+        assert_eq!(node(&scanner, "event3"), "reader");
+        // unplugged, and not an event node
+        assert_eq!(node(&scanner, "event4"), "none");
+        assert_eq!(node(&scanner, "mouse0"), "none");
+        std::fs::remove_file(dir.join("event0")).unwrap();
+        scanner.scan().unwrap();
+        assert_eq!(node(&scanner, "event0"), "none");
+        assert_eq!(node(&scanner, "event1"), "open failed");
+        drop(done_sender);
+        std::fs::remove_dir_all(&dir).unwrap();
+        for node in scanner.devices.into_values() {
+            if let Node::Reader(reader) = node {
+                reader.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// runs the driver on the emulator and compares the frames against the model's golden images
+// for --fixed-point, which rasterizes with the same integer pipeline as the hardware.
+
+use std::path::Path;
+
+use clap::Parser;
+use image::{Rgb, RgbImage};
+use rs_common::{
+    assets::AssetLoader,
+    input::NullInputSource,
+    runner::{Runner, Session},
+};
+
+use crate::{Cli, HwBackend, emu::Emulator};
+
+const ARGS: [&str; 5] = ["driver", "--width", "320", "--height", "240"];
+
+fn model_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../model"))
+}
+
+fn check_scene(name: &str, scene: &str, frames: &[usize]) {
+    let cli = Cli::parse_from(ARGS);
+    let (width, height) = (cli.width as u32, cli.height as u32);
+    let backend = HwBackend::new(Emulator::new(), cli);
+    let session = Session::new(scene, 0);
+    let mut loader = AssetLoader::new(model_dir().join("tests/assets"));
+    let runner = Runner::with_assets(backend, NullInputSource, &session, &mut loader)
+        .unwrap_or_else(|| panic!("unknown scene {scene}"));
+    runner.run_frames(frames.iter().max().unwrap() + 1, |backend, i| {
+        if !frames.contains(&i) {
+            return;
+        }
+        // the emulator completes all work as soon as it is issued, so the frame is on screen
+        let scanout = backend.scanout();
+        let actual = RgbImage::from_fn(width, height, |x, y| {
+            let pixel = scanout[(y * 640 + x) as usize];
+            Rgb([(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+        });
+        let path = model_dir().join(format!("tests/golden/{name}_{i:03}.png"));
+        let expected = image::open(&path)
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+            .into_rgb8();
+        let differing = actual
+            .pixels()
+            .zip(expected.pixels())
+            .filter(|(a, e)| a != e)
+            .count();
+        assert!(
+            differing == 0,
+            "{name} frame {i}: {differing} pixels differ from {}",
+            path.display()
+        );
+    });
+}
+
+#[test]
+fn cube() {
+    check_scene("cube_fixed_point", "Cube", &[0, 30]);
+}
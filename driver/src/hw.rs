@@ -1,10 +1,14 @@
 use std::{
+    fmt,
     fs::{File, OpenOptions},
     os::unix::fs::OpenOptionsExt,
+    time::{Duration, Instant},
 };
 
 use memmap::MmapOptions;
 
+use crate::debug::STATS_ROWS;
+
 pub const MEM_START: u32 = 0x10000000;
 pub const MEM_LEN: u32 = 16 * 1024 * 1024;
 pub const REG_START: u32 = 0xFF200000;
@@ -24,18 +28,28 @@ pub const B_CONTROL_START: u32 = 1;
 pub const B_CONTROL_FLUSH: u32 = 2;
 pub const B_CONTROL_INVALIDATE_DEPTH: u32 = 4;
 pub const B_CONTROL_CLEAR: u32 = 8;
+// the length field of a start command is 16 bits
+pub const MAX_DRAW_LEN: u32 = 0xffff;
 
 pub const R_DEPTH_BUFFER: u32 = 0x010;
 pub const R_DEPTH_MODE: u32 = 0x014;
+// the DepthMode is in the low 3 bits
+pub const B_DEPTH_WRITE: u32 = 8;
 pub const R_CLEAR_ADDR: u32 = 0x018;
 pub const R_CLEAR_STRIDE: u32 = 0x01C;
 pub const R_CLEAR_WIDTH_HEIGHT: u32 = 0x020;
 pub const R_CLEAR_DATA: u32 = 0x024;
 
 pub const R_TEXTURE_EN: u32 = 0x028;
+pub const B_TEXTURE_EN: u32 = 1;
 pub const R_TEXTURE_ADDR: u32 = 0x02C;
+pub const R_TEXTURE_BORDER: u32 = 0x034;
 
 pub const R_STATS_ENABLED: u32 = 0x030;
+// four counters per row of STATS_ROWS
+pub const R_STATS: u32 = 0x800;
+
+pub const R_CMD_ADDR: u32 = 0x038;
 
 pub const R_TEXT_EN: u32 = 0x080;
 pub const B_TEXT_EN: u32 = 1;
@@ -44,13 +58,184 @@ pub const B_TEXT_ACCESS_FONT: u32 = 3 << 30;
 pub const B_TEXT_ACCESS_TEXT: u32 = 2 << 30;
 pub const R_TEXT_TRANSPARENT: u32 = 0x088;
 
-pub struct Hw {
+// the register file and memory the driver talks to, DevMem is the real hardware and
+// emu::Emulator a software version of it.
+pub trait Hw {
+    fn get_reg(&mut self, addr: u32) -> u32;
+    fn set_reg(&mut self, addr: u32, value: u32);
+    // all MEM_LEN bytes of vram, starting at MEM_START
+    fn mem(&mut self) -> &mut [u8];
+
+    unsafe fn read<T>(&mut self, addr: u32) -> T {
+        let ptr = self.mem_mut(addr, size_of::<T>() as u32).as_mut_ptr() as *mut T;
+        unsafe { ptr.read_volatile() }
+    }
+    fn write<T>(&mut self, addr: u32, value: T) {
+        let ptr = self.mem_mut(addr, size_of::<T>() as u32).as_mut_ptr() as *mut T;
+        unsafe { ptr.write_volatile(value) }
+    }
+    fn mem_mut(&mut self, addr: u32, len: u32) -> &mut [u8] {
+        assert!(addr >= MEM_START);
+        assert!(addr + len <= MEM_START + MEM_LEN);
+        &mut self.mem()[(addr - MEM_START) as usize..(addr + len - MEM_START) as usize]
+    }
+    fn start_clear(&mut self, addr: u32, stride: u16, width: u16, height: u16, value: u32) {
+        assert!(addr % 64 == 0 && stride % 4 == 0 && width % 4 == 0);
+        self.set_reg(R_CLEAR_ADDR, addr);
+        self.set_reg(R_CLEAR_STRIDE, stride as u32 / 4);
+        self.set_reg(
+            R_CLEAR_WIDTH_HEIGHT,
+            width as u32 / 4 | (height as u32) << 16,
+        );
+        self.set_reg(R_CLEAR_DATA, value);
+        self.set_reg(R_CONTROL, B_CONTROL_CLEAR);
+    }
+    // older bitstreams ignore writes to the registers the driver relies on, so write
+    // them and check they read back
+    fn check_bitstream(&mut self) -> Result<(), String> {
+        let depth_mode = self.get_reg(R_DEPTH_MODE);
+        self.set_reg(R_CMD_ADDR, MEM_START);
+        self.set_reg(R_DEPTH_MODE, depth_mode | B_DEPTH_WRITE);
+        let has_cmd_addr = self.get_reg(R_CMD_ADDR) == MEM_START;
+        let has_depth_write = self.get_reg(R_DEPTH_MODE) & B_DEPTH_WRITE != 0;
+        self.set_reg(R_DEPTH_MODE, depth_mode);
+        let missing: Vec<&str> = [("cmd_addr", has_cmd_addr), ("depth write", has_depth_write)]
+            .into_iter()
+            .filter_map(|(name, present)| (!present).then_some(name))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "bitstream too old: it lacks {}, rebuild it from hw/",
+                missing.join(" and ")
+            ))
+        }
+    }
+    fn clear_busy(&mut self) -> bool {
+        self.get_reg(R_STATUS) & B_STATUS_CLEAR_BUSY != 0
+    }
+    fn clear(
+        &mut self,
+        addr: u32,
+        stride: u16,
+        width: u16,
+        height: u16,
+        value: u32,
+        timeout: Duration,
+    ) -> Result<(), HwTimeout> {
+        self.start_clear(addr, stride, width, height, value);
+        self.wait_until("the clear engine", timeout, |hw| !hw.clear_busy())
+    }
+    // rasterizes len triangles read from addr and flushes them through the pipeline,
+    // poll_flushed tells when they are done.
+    fn start_draw(&mut self, addr: u32, len: u32) {
+        assert!(len > 0 && len <= MAX_DRAW_LEN);
+        self.set_reg(R_CMD_ADDR, addr);
+        self.set_reg(R_CONTROL, B_CONTROL_START | len << 16);
+        self.set_reg(R_CONTROL, B_CONTROL_FLUSH);
+    }
+    // acknowledges the flush if it has completed
+    fn poll_flushed(&mut self) -> bool {
+        let flushed = self.get_reg(R_STATUS) & B_STATUS_FLUSHED != 0;
+        if flushed {
+            self.set_reg(R_STATUS, B_STATUS_FLUSHED);
+        }
+        flushed
+    }
+    fn flush_pipeline(&mut self, timeout: Duration) -> Result<(), HwTimeout> {
+        self.set_reg(R_CONTROL, B_CONTROL_FLUSH);
+        self.wait_until("the pipeline to flush", timeout, Self::poll_flushed)
+    }
+    // acknowledges the vsync if the video output is in one
+    fn poll_vsync(&mut self) -> bool {
+        let status = self.get_reg(R_STATUS);
+        let vsync = status & B_STATUS_IN_VSYNC != 0 && status & B_STATUS_SEEN_VSYNC != 0;
+        if vsync {
+            self.set_reg(R_STATUS, B_STATUS_SEEN_VSYNC);
+        }
+        vsync
+    }
+    fn wait_for_vsync(&mut self, timeout: Duration) -> Result<(), HwTimeout> {
+        self.wait_until("vsync", timeout, Self::poll_vsync)
+    }
+    fn wait_until(
+        &mut self,
+        waiting_for: &'static str,
+        timeout: Duration,
+        mut done: impl FnMut(&mut Self) -> bool,
+    ) -> Result<(), HwTimeout> {
+        let start = Instant::now();
+        while !done(self) {
+            if start.elapsed() > timeout {
+                return Err(self.timeout_error(waiting_for, timeout));
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+    fn timeout_error(&mut self, waiting_for: &'static str, timeout: Duration) -> HwTimeout {
+        HwTimeout {
+            waiting_for,
+            timeout,
+            regs: TIMEOUT_REGS.map(|(_, reg)| self.get_reg(reg)),
+            stats: (0..STATS_ROWS.len() as u32 * 4)
+                .map(|i| self.get_reg(R_STATS + 4 * i))
+                .collect(),
+        }
+    }
+}
+
+// the registers that tell where the hardware got stuck
+const TIMEOUT_REGS: [(&str, u32); 6] = [
+    ("status", R_STATUS),
+    ("control", R_CONTROL),
+    ("cmd_addr", R_CMD_ADDR),
+    ("render_target", R_RENDER_TARGET),
+    ("depth_buffer", R_DEPTH_BUFFER),
+    ("texture_en", R_TEXTURE_EN),
+];
+
+// a wait for the hardware that didn't complete in time
+#[derive(Debug, Clone)]
+pub struct HwTimeout {
+    pub waiting_for: &'static str,
+    pub timeout: Duration,
+    // the values of TIMEOUT_REGS
+    pub regs: [u32; TIMEOUT_REGS.len()],
+    // the counters of each stats row, see StatsCollection
+    pub stats: Vec<u32>,
+}
+
+impl fmt::Display for HwTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "hardware timed out after {:?} waiting for {}",
+            self.timeout, self.waiting_for
+        )?;
+        for ((name, _), value) in TIMEOUT_REGS.iter().zip(self.regs) {
+            write!(f, " {name}={value:#010x}")?;
+        }
+        for (row, counters) in STATS_ROWS.iter().zip(self.stats.chunks(4)) {
+            write!(f, "\n {row:10}")?;
+            for counter in counters {
+                write!(f, "{counter:12}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HwTimeout {}
+
+pub struct DevMem {
     file: File,
     regs: memmap::MmapMut,
     mem: memmap::MmapMut,
 }
 
-impl Hw {
+impl DevMem {
     pub fn new() -> Result<Self, std::io::Error> {
         let file = OpenOptions::new()
             .read(true)
@@ -69,71 +254,27 @@ impl Hw {
                 .offset(MEM_START as u64)
                 .map_mut(&file)?
         };
-        Ok(Hw { file, regs, mem })
+        Ok(DevMem { file, regs, mem })
     }
-    pub unsafe fn read<T>(&mut self, addr: u32) -> T {
-        assert!(addr >= MEM_START);
-        assert!(addr + size_of::<T>() as u32 <= MEM_START + MEM_LEN);
-        unsafe {
-            let ptr = self.mem.as_mut_ptr().byte_add((addr - MEM_START) as usize) as *mut T;
-            ptr.read_volatile()
-        }
-    }
-    pub fn write<T>(&mut self, addr: u32, value: T) {
-        assert!(addr >= MEM_START);
-        assert!(addr + size_of::<T>() as u32 <= MEM_START + MEM_LEN);
-        unsafe {
-            let ptr = self.mem.as_mut_ptr().byte_add((addr - MEM_START) as usize) as *mut T;
-            ptr.write_volatile(value);
-        }
-    }
-    pub fn mem_mut(&mut self, addr: u32, len: u32) -> &mut [u8] {
-        assert!(addr >= MEM_START);
-        assert!(addr + len <= MEM_START + MEM_LEN);
-        &mut self.mem[(addr - MEM_START) as usize..(addr + len - MEM_START) as usize]
-    }
-    pub fn get_reg(&mut self, addr: u32) -> u32 {
+}
+
+impl Hw for DevMem {
+    fn get_reg(&mut self, addr: u32) -> u32 {
         assert!(addr < REG_LEN && (addr & 3) == 0);
         unsafe {
             let ptr = self.regs.as_mut_ptr().byte_add(addr as usize) as *mut u32;
             ptr.read_volatile()
         }
     }
-    pub fn set_reg(&mut self, addr: u32, value: u32) {
+    fn set_reg(&mut self, addr: u32, value: u32) {
         assert!(addr < REG_LEN && (addr & 3) == 0);
         unsafe {
             let ptr = self.regs.as_mut_ptr().byte_add(addr as usize) as *mut u32;
             ptr.write_volatile(value);
         }
     }
-    pub fn clear(&mut self, addr: u32, stride: u16, width: u16, height: u16, value: u32) {
-        assert!(addr % 64 == 0 && stride % 4 == 0 && width % 4 == 0);
-        self.set_reg(R_CLEAR_ADDR, addr);
-        self.set_reg(R_CLEAR_STRIDE, stride as u32 / 4);
-        self.set_reg(
-            R_CLEAR_WIDTH_HEIGHT,
-            width as u32 / 4 | (height as u32) << 16,
-        );
-        self.set_reg(R_CLEAR_DATA, value);
-        self.set_reg(R_CONTROL, B_CONTROL_CLEAR);
-        while self.get_reg(R_STATUS) & B_STATUS_CLEAR_BUSY != 0 {
-            std::thread::yield_now();
-        }
-    }
-    pub fn flush_pipeline(&mut self) {
-        self.set_reg(R_CONTROL, B_CONTROL_FLUSH);
-        while self.get_reg(R_STATUS) & B_STATUS_FLUSHED == 0 {
-            std::thread::yield_now();
-        }
-        self.set_reg(R_STATUS, B_STATUS_FLUSHED);
-    }
-    pub fn wait_for_vsync(&mut self) {
-        while self.get_reg(R_STATUS) & B_STATUS_IN_VSYNC == 0
-            || self.get_reg(R_STATUS) & B_STATUS_SEEN_VSYNC == 0
-        {
-            std::thread::yield_now();
-        }
-        self.set_reg(R_STATUS, B_STATUS_SEEN_VSYNC);
+    fn mem(&mut self) -> &mut [u8] {
+        &mut self.mem
     }
 }
 
@@ -161,7 +302,25 @@ impl VramAlloc {
                 return Some(ret as u32);
             }
         }
-        return None;
+        None
+    }
+    // hands a range from alloc back, merging it with the free ranges around it.
+    pub fn free(&mut self, start: u32, len: usize) {
+        let start = start as usize;
+        let end = start + len;
+        // free is sorted by start, alloc and reserve only ever shrink or split ranges
+        let i = self.free.partition_point(|&(r_start, _)| r_start < start);
+        assert!(i == 0 || self.free[i - 1].0 + self.free[i - 1].1 <= start);
+        assert!(i == self.free.len() || end <= self.free[i].0);
+        self.free.insert(i, (start, len));
+        if i + 1 < self.free.len() && self.free[i + 1].0 == end {
+            self.free[i].1 += self.free[i + 1].1;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == start {
+            self.free[i - 1].1 += self.free[i].1;
+            self.free.remove(i);
+        }
     }
     pub fn reserve(&mut self, start: usize, len: usize) {
         let end = start + len;
@@ -181,3 +340,71 @@ impl VramAlloc {
         }).collect();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_merges_neighbours() {
+        let mut vram = VramAlloc::new(0, 100);
+        let a = vram.alloc(10).unwrap();
+        let b = vram.alloc(20).unwrap();
+        let c = vram.alloc(30).unwrap();
+        assert_eq!((a, b, c), (0, 10, 30));
+        assert_eq!(vram.free, [(60, 40)]);
+        // no neighbour is free
+        vram.free(b, 20);
+        assert_eq!(vram.free, [(10, 20), (60, 40)]);
+        // merges with the ranges before and after it
+        vram.free(c, 30);
+        assert_eq!(vram.free, [(10, 90)]);
+        // merges with the range after it, at the start of memory
+        vram.free(a, 10);
+        assert_eq!(vram.free, [(0, 100)]);
+        // merges with the range before it
+        let [a, b, c] = [10, 20, 70].map(|len| vram.alloc(len).unwrap());
+        assert!(vram.free.is_empty());
+        vram.free(a, 10);
+        vram.free(b, 20);
+        assert_eq!(vram.free, [(0, 30)]);
+        vram.free(c, 70);
+        assert_eq!(vram.free, [(0, 100)]);
+        // freed ranges are found again by alloc
+        assert_eq!(vram.alloc(100), Some(0));
+        assert_eq!(vram.alloc(1), None);
+    }
+
+    // the registers of a bitstream from before cmd_addr and the depth write bit
+    struct OldBitstream {
+        depth_mode: u32,
+    }
+
+    impl Hw for OldBitstream {
+        fn get_reg(&mut self, addr: u32) -> u32 {
+            match addr {
+                R_DEPTH_MODE => self.depth_mode,
+                _ => 0,
+            }
+        }
+        fn set_reg(&mut self, addr: u32, value: u32) {
+            if addr == R_DEPTH_MODE {
+                self.depth_mode = value & 7;
+            }
+        }
+        fn mem(&mut self) -> &mut [u8] {
+            &mut []
+        }
+    }
+
+    #[test]
+    fn old_bitstream_is_rejected() {
+        let mut hw = OldBitstream { depth_mode: 4 };
+        let err = hw.check_bitstream().unwrap_err();
+        assert!(err.starts_with("bitstream too old"), "{err}");
+        assert!(err.contains("cmd_addr and depth write"), "{err}");
+        assert_eq!(hw.depth_mode, 4);
+        let mut emu = crate::emu::Emulator::new();
+        assert_eq!(emu.check_bitstream(), Ok(()));
+    }
+}
@@ -1,206 +1,620 @@
 #![allow(dead_code)]
 
-use crate::{debug::*, hw::*};
+use crate::{
+    debug::*,
+    hw::*,
+    queue::{CommandBuffer, Op, Queue},
+};
 use clap::Parser;
-use evdev::EvdevSource;
+use evdev::{DeviceFilter, EvdevConfig, EvdevSource};
+use net::NetSource;
 use rs_common::{
-    assets::AssetLoader, input::{InputSource, InputState}, render::{self, Backend, BackendTriangle, Context, HEIGHT, WIDTH}, scene
+    input::{ActionMap, InputRecorder, InputSource, Key, MergedSource},
+    mesh::Color,
+    render::{
+        self, AlphaMode, AlphaState, Backend, BackendTriangle, DepthMode, DepthState, SamplerState,
+        TILE_SIZE, Viewport, WrapMode,
+        capture::{Capture, Recorder},
+    },
+    runner::{Runner, Session, random_seed},
+};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
-use std::time::Instant;
 
 pub mod debug;
+pub mod emu;
 pub mod evdev;
 pub mod hw;
+pub mod net;
+pub mod queue;
+pub mod signal;
 
-#[repr(C)]
-#[derive(Debug, Clone)]
-struct HwTriangle {
-    edge_vec: [[i32; 3]; 3],
-    uv: [[i32; 2]; 3],
-    rgb: [u32; 3],
-    min_x: u16,
-    min_y: u16,
-    max_x: u16,
-    max_y: u16,
-}
-
-impl HwTriangle {
-    fn new(c: &BackendTriangle) -> Self {
-        let mut edge_vec = [[0; 3]; 3];
-        for i in 0..3 {
-            for j in 0..3 {
-                edge_vec[i][j] = (c.edge_mat[j][i] * (1 << 20) as f64) as i32;
-            }
-        }
-        let uv =
-            c.uv.map(|row| row.map(|n| (n * (1 << 11) as f64) as i32 + (1 << 17)));
-        HwTriangle {
-            edge_vec,
-            uv,
-            rgb: c.rgb,
-            min_x: c.bbox.min_x as u16,
-            min_y: c.bbox.min_y as u16,
-            max_x: c.bbox.max_x as u16,
-            max_y: c.bbox.max_y as u16,
-        }
-    }
+#[cfg(test)]
+mod golden;
+
+#[derive(Debug, Clone, Copy)]
+struct HwTexture {
+    addr: u32,
+    en: u32,
+    size: usize,
+}
+
+struct HwRenderTarget {
+    viewport: Viewport,
+    fb: u32,
+    depth_buffer: u32,
+    texture: HwTexture,
 }
 
-struct HwBackend {
-    hw: Hw,
+struct HwBackend<H: Hw> {
+    hw: H,
     cli: Cli,
-    render_fb: u32,
-    display_fb: u32,
+    viewport: Viewport,
+    // frame n renders into framebuffers[n % 2] while the other one is displayed
+    framebuffers: [u32; 2],
     depth_buffer: u32,
-    cmd_ptr: u32,
-    cmd_len: u32,
+    render_targets: Vec<HwRenderTarget>,
+    current_target: Option<usize>,
+    texture_en: u32,
+    wrap_mode: u32,
     stats_collection: StatsCollection,
     text_display: TextDisplay,
     vram_alloc: VramAlloc,
-    frame_start: Instant,
+    queue: Queue,
+    // the op issued last and when, if it may not have completed yet
+    in_flight: Option<(InFlight, Instant)>,
+    // indexed like framebuffers
+    command_buffers: [CommandBuffer; 2],
+    queued_frames: u64,
+    presented_frames: u64,
+    // vram that queued ops may still use, freed once the frames queued so far are presented
+    pending_frees: Vec<(u64, u32, usize)>,
+    last_present: Instant,
+    // set after the hardware had to be reset, the rest of the frame isn't drawn
+    skip_frame: bool,
+    // recoveries since the last frame was presented
+    failures: u32,
+    // with --restore-display, the framebuffer shown before the driver started and its pixels
+    saved_display: Option<(u32, Vec<u8>)>,
+    // false if the display register didn't point at a framebuffer in vram at startup, then
+    // the display is left alone on exit
+    blank_display: bool,
+    // alpha states the hardware can't do are reported once
+    warned_alpha: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum InFlight {
+    Clear,
+    Draw,
+    Present(u32),
 }
 
-impl std::ops::Deref for HwBackend {
-    type Target = Hw;
+impl<H: Hw> std::ops::Deref for HwBackend<H> {
+    type Target = H;
     fn deref(&self) -> &Self::Target {
         &self.hw
     }
 }
-impl std::ops::DerefMut for HwBackend {
+impl<H: Hw> std::ops::DerefMut for HwBackend<H> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.hw
     }
 }
 
-impl HwBackend {
-    fn new(mut hw: Hw, cli: Cli) -> HwBackend {
-        hw.set_reg(R_DEPTH_MODE, if cli.disable_depth_buffer { 0 } else { 4 });
-        hw.set_reg(R_TEXTURE_EN, 0);
-        hw.set_reg(R_STATS_ENABLED, 0);
+// the video output always scans out 640x480 with a 640 pixel stride,
+// smaller viewports are rendered into the top left corner.
+const FB_WIDTH: usize = 640;
+const FB_HEIGHT: usize = 480;
+
+// the depth cache tags cover 16x16 tiles and each row of tags
+// occupies 32 rows of a 2048 word wide buffer.
+const DEPTH_BUFFER_STRIDE: usize = 2048;
+
+// a hardware that doesn't present a frame after this many resets is broken for good
+const MAX_RECOVERIES: u32 = 3;
+
+// color registers hold 0x00BBGGRR, the hardware has no alpha
+fn color_reg(color: Color) -> u32 {
+    color.as_u32() & 0xff_ffff
+}
+
+fn depth_buffer_rows(viewport: Viewport) -> usize {
+    viewport.height.div_ceil(16 * TILE_SIZE) * 32
+}
+
+impl<H: Hw> HwBackend<H> {
+    fn new(mut hw: H, cli: Cli) -> Self {
+        hw.check_bitstream().unwrap_or_else(|e| panic!("{e}"));
+        let viewport = Viewport::new(cli.width, cli.height);
+        assert!(viewport.width <= FB_WIDTH && viewport.height <= FB_HEIGHT);
+        assert!(viewport.width.is_multiple_of(4));
+        // a freshly programmed FPGA can have anything in the display register
+        let fb = hw.get_reg(R_DISPLAY_FB);
+        let len = (FB_WIDTH * FB_HEIGHT * 4) as u32;
+        let blank_display =
+            fb >= MEM_START && fb <= MEM_START + MEM_LEN - len && fb.is_multiple_of(64);
+        if !blank_display {
+            eprintln!("the display shows {fb:#x}, which isn't in vram, leaving it alone on exit");
+        }
+        let saved_display =
+            (cli.restore_display && blank_display).then(|| (fb, hw.mem_mut(fb, len).to_vec()));
         let mut vram_alloc = VramAlloc::new(MEM_START as usize, MEM_LEN as usize);
-        // FIXME: don't statically allocate command buffer
-        vram_alloc.reserve(0x10200000, 1024 * 1024);
-        let render_fb = vram_alloc.alloc(WIDTH * HEIGHT * 4).unwrap();
-        let display_fb = vram_alloc.alloc(WIDTH * HEIGHT * 4).unwrap();
-        // FIXME: don't reserve so much depth buffer
-        let depth_buffer = vram_alloc.alloc(2048 * 256 * 4).unwrap();
-        hw.set_reg(R_DEPTH_BUFFER, depth_buffer);
-        let stats_collection = StatsCollection::new();
-        let mut text_display = TextDisplay::new();
-        text_display.init(&mut hw);
-        HwBackend {
+        let framebuffers = [0, 1].map(|_| vram_alloc.alloc(FB_WIDTH * FB_HEIGHT * 4).unwrap());
+        let depth_buffer = vram_alloc
+            .alloc(DEPTH_BUFFER_STRIDE * depth_buffer_rows(viewport) * 4)
+            .unwrap();
+        let mut backend = HwBackend {
             hw,
             cli,
-            render_fb,
-            display_fb,
+            viewport,
+            framebuffers,
             depth_buffer,
-            cmd_ptr: 0,
-            cmd_len: 0,
-            stats_collection,
-            text_display,
+            render_targets: Vec::new(),
+            current_target: None,
+            texture_en: 0,
+            wrap_mode: 0,
+            stats_collection: StatsCollection::new(),
+            text_display: TextDisplay::new(),
             vram_alloc,
-            frame_start: Instant::now(),
+            queue: Queue::default(),
+            in_flight: None,
+            command_buffers: Default::default(),
+            queued_frames: 0,
+            presented_frames: 0,
+            pending_frees: Vec::new(),
+            last_present: Instant::now(),
+            skip_frame: false,
+            failures: 0,
+            saved_display,
+            blank_display,
+            warned_alpha: false,
+        };
+        backend.init_regs();
+        backend
+    }
+    // puts the registers into the state expected between frames
+    fn init_regs(&mut self) {
+        let hw = &mut self.hw;
+        hw.set_reg(R_CONTROL, 0);
+        hw.set_reg(R_STATUS, B_STATUS_SEEN_VSYNC | B_STATUS_FLUSHED);
+        hw.set_reg(R_TEXTURE_EN, 0);
+        hw.set_reg(R_STATS_ENABLED, 0);
+        hw.set_reg(R_DEPTH_BUFFER, self.depth_buffer);
+        self.text_display.init(hw);
+    }
+    // the frame being built, as an index into framebuffers and command_buffers
+    fn frame_index(&self) -> usize {
+        (self.queued_frames % 2) as usize
+    }
+    // issues queued ops until one has to be waited for, returns true once the queue is empty.
+    fn poll(&mut self) -> bool {
+        match self.try_poll() {
+            Ok(done) => done,
+            Err(err) => {
+                self.recover(err);
+                false
+            }
         }
     }
-    fn start_frame(&mut self) {
-        if self.cli.show_stats {
-            self.stats_collection.reset(&mut self.hw);
+    fn try_poll(&mut self) -> Result<bool, HwTimeout> {
+        loop {
+            if let Some((op, since)) = self.in_flight {
+                let done = match op {
+                    InFlight::Clear => !self.hw.clear_busy(),
+                    InFlight::Draw => self.hw.poll_flushed(),
+                    InFlight::Present(_) => self.hw.poll_vsync(),
+                };
+                if !done {
+                    let (waiting_for, timeout) = self.timeout(op);
+                    if since.elapsed() > timeout {
+                        return Err(self.hw.timeout_error(waiting_for, timeout));
+                    }
+                    return Ok(false);
+                }
+                if let InFlight::Present(fb) = op {
+                    self.finish_present(fb);
+                }
+            }
+            self.in_flight = None;
+            let Some(op) = self.queue.pop() else {
+                return Ok(true);
+            };
+            match op {
+                Op::SetReg(reg, value) => self.hw.set_reg(reg, value),
+                Op::Clear {
+                    addr,
+                    stride,
+                    width,
+                    height,
+                    value,
+                } => {
+                    self.hw.start_clear(addr, stride, width, height, value);
+                    self.in_flight = Some((InFlight::Clear, Instant::now()));
+                }
+                Op::Draw { addr, len } => {
+                    self.hw.start_draw(addr, len);
+                    self.in_flight = Some((InFlight::Draw, Instant::now()));
+                }
+                Op::Copy {
+                    from,
+                    from_stride,
+                    to,
+                    row_len,
+                    rows,
+                } => {
+                    for y in 0..rows {
+                        let row = self.mem_mut(from + y * from_stride, row_len).to_vec();
+                        self.mem_mut(to + y * row_len, row_len)
+                            .copy_from_slice(&row);
+                    }
+                }
+                Op::StatsStart => {
+                    self.stats_collection.reset(&mut self.hw);
+                    self.stats_collection.start(&mut self.hw);
+                }
+                Op::StatsStop => self.stats_collection.stop(&mut self.hw),
+                Op::Present(fb) => self.in_flight = Some((InFlight::Present(fb), Instant::now())),
+            }
         }
-        self.cmd_ptr = 0x10200000;
-        self.cmd_len = 0;
-        self.frame_start = Instant::now();
-
-        self.hw.clear(self.render_fb, 640, 640, 480, 0x66666666u32);
-        self.hw.clear(self.depth_buffer, 2048, 2048, 256, 0);
-        self.hw.set_reg(R_CONTROL, B_CONTROL_INVALIDATE_DEPTH);
-
-        self.hw.set_reg(R_RENDER_TARGET, self.render_fb);
-        if self.cli.show_stats {
-            self.stats_collection.start(&mut self.hw);
+    }
+    fn timeout(&self, op: InFlight) -> (&'static str, Duration) {
+        let (waiting_for, ms) = match op {
+            InFlight::Clear => ("the clear engine", self.cli.clear_timeout_ms),
+            InFlight::Draw => ("the pipeline to flush", self.cli.draw_timeout_ms),
+            InFlight::Present(_) => ("vsync", self.cli.vsync_timeout_ms),
+        };
+        (waiting_for, Duration::from_millis(ms))
+    }
+    // the hardware got stuck: drops all queued work, sets up the registers again and skips the
+    // frame being built.
+    fn recover(&mut self, err: HwTimeout) {
+        self.failures += 1;
+        if self.failures > MAX_RECOVERIES {
+            panic!("{err}\ngiving up after resetting the hardware {MAX_RECOVERIES} times");
         }
+        eprintln!("{err}\nresetting the hardware and skipping the frame");
+        self.in_flight = None;
+        self.queue.restart();
+        self.init_regs();
+        // the frames queued so far will never be shown, nothing uses their vram anymore
+        self.presented_frames = self.queued_frames;
+        self.free_pending();
+        self.skip_frame = true;
     }
-    fn render_frame(&mut self) {
-        let mut hw = &mut self.hw;
-
-        if self.cli.show_stats {
-            self.stats_collection.stop(&mut hw);
+    fn wait_idle(&mut self) {
+        while !self.poll() {
+            std::thread::yield_now();
         }
+    }
+    // waits until no more than `frames` frames are queued but not yet presented
+    fn wait_for_frames(&mut self, frames: u64) {
+        while self.queued_frames - self.presented_frames > frames {
+            self.poll();
+            std::thread::yield_now();
+        }
+    }
+    // frees the vram of pending_frees that no queued op uses anymore
+    fn free_pending(&mut self) {
+        let presented = self.presented_frames;
+        let vram_alloc = &mut self.vram_alloc;
+        self.pending_frees.retain(|&(frame, addr, size)| {
+            if frame < presented {
+                vram_alloc.free(addr, size);
+            }
+            frame >= presented
+        });
+    }
+    // runs at the vsync that makes fb visible
+    fn finish_present(&mut self, fb: u32) {
+        let hw = &mut self.hw;
+        hw.set_reg(R_DISPLAY_FB, fb);
+        self.presented_frames += 1;
+        self.failures = 0;
+        self.free_pending();
+        let hw = &mut self.hw;
 
-        let render_done = Instant::now();
-
-        hw.wait_for_vsync();
-        std::mem::swap(&mut self.render_fb, &mut self.display_fb);
-        hw.set_reg(R_DISPLAY_FB, self.display_fb);
-
-        self.text_display.clear_all(&mut hw);
+        let now = Instant::now();
+        self.text_display.clear_all(hw);
         if self.cli.show_fps {
             self.text_display.print(
-                &mut hw,
+                hw,
                 1,
                 1,
                 &format!(
                     "FPS:     {:5.1}\n",
-                    1.0 / (render_done - self.frame_start).as_secs_f64(),
+                    1.0 / (now - self.last_present).as_secs_f64(),
                 ),
             );
         }
         if self.cli.show_stats {
-            self.stats_collection.print(&mut hw);
+            self.stats_collection.print(hw);
+        }
+        self.last_present = now;
+    }
+    fn update_texture_en(&mut self) {
+        if self.texture_en & B_TEXTURE_EN != 0 {
+            self.queue
+                .set_reg(R_TEXTURE_EN, self.texture_en | self.wrap_mode << 1);
+        } else {
+            self.queue.set_reg(R_TEXTURE_EN, 0);
+        }
+    }
+    // leaves the current render target, copying its contents into the texture.
+    fn resolve_render_target(&mut self) {
+        if let Some(i) = self.current_target.take() {
+            // PixelOut always writes with a 640 pixel stride, which the texture
+            // unit can't sample from, so copy the rows into a packed texture.
+            let rt = &self.render_targets[i];
+            self.queue.push(Op::Copy {
+                from: rt.fb,
+                from_stride: FB_WIDTH as u32 * 4,
+                to: rt.texture.addr,
+                row_len: rt.viewport.width as u32 * 4,
+                rows: rt.viewport.height as u32,
+            });
         }
     }
 }
 
 fn translate_texture_type(ty: &render::TextureType) -> Option<u32> {
-    let f = |x: usize| {
-        if x & (x - 1) != 0 || x < 8 || x > 1024 {
-            None
-        } else {
-            Some(x.trailing_zeros() - 3)
-        }
-    };
-    Some(1 | f(ty.width)? << 4 | f(ty.height)? << 8 | f(ty.stride)? << 12)
+    let f = render::fixed::texture_size_log2;
+    Some(B_TEXTURE_EN | f(ty.width)? << 4 | f(ty.height)? << 8 | f(ty.stride)? << 12)
+}
+
+fn translate_wrap_mode(wrap: WrapMode) -> u32 {
+    match wrap {
+        WrapMode::Wrap => 0,
+        WrapMode::ClampToEdge => 1,
+        WrapMode::ClampToBorder => 2,
+    }
 }
 
-impl Backend for HwBackend {
-    type Texture = (u32, u32);
+fn translate_depth_mode(mode: DepthMode) -> u32 {
+    match mode {
+        DepthMode::Always => 0,
+        DepthMode::Never => 1,
+        DepthMode::Lt => 2,
+        DepthMode::Le => 3,
+        DepthMode::Gt => 4,
+        DepthMode::Ge => 5,
+        DepthMode::Eq => 6,
+        DepthMode::Ne => 7,
+    }
+}
+
+impl<H: Hw> Backend for HwBackend<H> {
+    type Texture = HwTexture;
+    type RenderTarget = usize;
     type Error = ();
 
+    fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
     fn load_texture(&mut self, texture: render::Texture) -> Result<Self::Texture, Self::Error> {
         if self.cli.textures_off {
-            Ok((0, 0))
+            Ok(HwTexture {
+                addr: 0,
+                en: 0,
+                size: 0,
+            })
         } else {
             let en = translate_texture_type(&texture.ty).unwrap();
             let size = texture.ty.stride * texture.ty.height * 4;
-            let vram = self.vram_alloc.alloc(size).unwrap();
-            self.mem_mut(vram, size as u32)
+            let addr = self.vram_alloc.alloc(size).unwrap();
+            self.mem_mut(addr, size as u32)
                 .copy_from_slice(&texture.data);
-            Ok((vram, en))
+            Ok(HwTexture { addr, en, size })
+        }
+    }
+
+    fn unload_texture(&mut self, texture: Self::Texture) {
+        if texture.size != 0 {
+            self.pending_frees
+                .push((self.queued_frames, texture.addr, texture.size));
         }
     }
 
     fn use_texture(&mut self, texture: Option<&Self::Texture>) {
-        if let Some(&(addr, en)) = texture {
-            self.hw.set_reg(R_TEXTURE_ADDR, addr);
-            self.hw.set_reg(R_TEXTURE_EN, en);
+        if let Some(texture) = texture {
+            self.texture_en = texture.en;
+            self.queue.set_reg(R_TEXTURE_ADDR, texture.addr);
         } else {
-            self.hw.set_reg(R_TEXTURE_EN, 0);
+            self.texture_en = 0;
+        }
+        self.update_texture_en();
+    }
+
+    fn create_render_target(
+        &mut self,
+        viewport: Viewport,
+    ) -> Result<(Self::RenderTarget, Self::Texture), Self::Error> {
+        let en = translate_texture_type(&render::TextureType {
+            width: viewport.width,
+            height: viewport.height,
+            stride: viewport.width,
+        })
+        .ok_or(())?;
+        if viewport.width > FB_WIDTH {
+            return Err(());
+        }
+        let fb = self
+            .vram_alloc
+            .alloc(FB_WIDTH * viewport.height * 4)
+            .ok_or(())?;
+        let depth_buffer = self
+            .vram_alloc
+            .alloc(DEPTH_BUFFER_STRIDE * depth_buffer_rows(viewport) * 4)
+            .ok_or(())?;
+        let size = viewport.width * viewport.height * 4;
+        let addr = self.vram_alloc.alloc(size).ok_or(())?;
+        let texture = HwTexture { addr, en, size };
+        self.render_targets.push(HwRenderTarget {
+            viewport,
+            fb,
+            depth_buffer,
+            texture,
+        });
+        Ok((self.render_targets.len() - 1, texture))
+    }
+
+    fn set_render_target(&mut self, target: Option<&Self::RenderTarget>, clear: Color) {
+        self.resolve_render_target();
+        let (viewport, fb, depth_buffer) = match target {
+            Some(&i) => {
+                let rt = &self.render_targets[i];
+                self.current_target = Some(i);
+                (rt.viewport, rt.fb, rt.depth_buffer)
+            }
+            None => (
+                self.viewport,
+                self.framebuffers[self.frame_index()],
+                self.depth_buffer,
+            ),
+        };
+        self.queue.push(Op::Clear {
+            addr: fb,
+            stride: FB_WIDTH as u16,
+            width: viewport.width as u16,
+            height: viewport.height as u16,
+            value: color_reg(clear),
+        });
+        self.queue.push(Op::Clear {
+            addr: depth_buffer,
+            stride: DEPTH_BUFFER_STRIDE as u16,
+            width: DEPTH_BUFFER_STRIDE as u16,
+            height: depth_buffer_rows(viewport) as u16,
+            value: 0,
+        });
+        self.queue.set_reg(R_RENDER_TARGET, fb);
+        self.queue.set_reg(R_DEPTH_BUFFER, depth_buffer);
+        self.queue
+            .push(Op::SetReg(R_CONTROL, B_CONTROL_INVALIDATE_DEPTH));
+        self.poll();
+    }
+
+    fn set_sampler(&mut self, sampler: SamplerState) {
+        self.wrap_mode = translate_wrap_mode(sampler.wrap);
+        self.queue
+            .set_reg(R_TEXTURE_BORDER, color_reg(sampler.border));
+        self.update_texture_en();
+    }
+
+    fn set_depth(&mut self, depth: DepthState) {
+        let mode = if self.cli.disable_depth_buffer {
+            DepthMode::Always
+        } else {
+            depth.mode
+        };
+        let write = if depth.write { B_DEPTH_WRITE } else { 0 };
+        self.queue
+            .set_reg(R_DEPTH_MODE, translate_depth_mode(mode) | write);
+    }
+
+    fn set_alpha(&mut self, alpha: AlphaState) {
+        // the hardware has no alpha test or blending, everything is drawn opaque
+        if alpha.mode != AlphaMode::Opaque && !self.warned_alpha {
+            eprintln!(
+                "alpha mode {:?} is not supported, drawing opaque",
+                alpha.mode
+            );
+            self.warned_alpha = true;
         }
     }
 
     fn draw(&mut self, triangles: &[BackendTriangle]) {
-        self.cmd_ptr = 0x10200000;
-        self.cmd_len = 0;
-        for t in triangles {
-            self.hw.write(self.cmd_ptr, HwTriangle::new(t));
-            self.cmd_ptr += size_of::<HwTriangle>() as u32;
-            self.cmd_len += 1;
+        if self.skip_frame {
+            return;
         }
-        self.hw
-            .set_reg(R_CONTROL, B_CONTROL_START | self.cmd_len << 16);
-        self.hw.flush_pipeline();
+        let frame = self.frame_index();
+        self.command_buffers[frame].draw(
+            &mut self.hw,
+            &mut self.vram_alloc,
+            &mut self.queue,
+            triangles,
+        );
+        self.poll();
+    }
+
+    fn begin_frame(&mut self, clear: Color) {
+        // the framebuffer and command buffer were last used two frames ago,
+        // that frame has to be done before they can be reused.
+        self.wait_for_frames(1);
+        self.skip_frame = false;
+        let frame = self.frame_index();
+        self.command_buffers[frame].reset();
+        if self.cli.show_stats {
+            self.queue.push(Op::StatsStart);
+        }
+        self.set_render_target(None, clear);
+    }
+
+    fn end_frame(&mut self) {
+        self.resolve_render_target();
+        if self.cli.show_stats {
+            self.queue.push(Op::StatsStop);
+        }
+        self.poll();
+    }
+
+    // doesn't wait for the frame, the next one can be built while this one is rendered.
+    fn present(&mut self) {
+        if self.skip_frame {
+            return;
+        }
+        let fb = self.framebuffers[self.frame_index()];
+        self.queue.push(Op::Present(fb));
+        self.queued_frames += 1;
+        self.poll();
     }
 }
 
+impl<H: Hw> Drop for HwBackend<H> {
+    // leaves the hardware idle, with the text overlay off and the screen blank or, with
+    // --restore-display, showing what it did before the driver started.
+    fn drop(&mut self) {
+        // giving up on the hardware panics, don't wait for it again
+        if !std::thread::panicking() {
+            self.wait_idle();
+        }
+        let hw = &mut self.hw;
+        hw.set_reg(R_STATS_ENABLED, 0);
+        hw.set_reg(R_TEXTURE_EN, 0);
+        hw.set_reg(R_TEXT_EN, 0);
+        if let Some((fb, pixels)) = self.saved_display.take() {
+            hw.mem_mut(fb, pixels.len() as u32).copy_from_slice(&pixels);
+            hw.set_reg(R_DISPLAY_FB, fb);
+        } else if self.blank_display {
+            let fb = hw.get_reg(R_DISPLAY_FB);
+            let timeout = Duration::from_millis(self.cli.clear_timeout_ms);
+            let (width, height) = (FB_WIDTH as u16, FB_HEIGHT as u16);
+            if let Err(err) = hw.clear(fb, width, width, height, 0, timeout) {
+                eprintln!("{err}\ncouldn't blank the screen");
+            }
+        }
+    }
+}
+
+fn parse_width(s: &str) -> Result<usize, String> {
+    let width: usize = s.parse().map_err(|e| format!("{e}"))?;
+    if width == 0 || width > FB_WIDTH || !width.is_multiple_of(4) {
+        return Err(format!("must be a multiple of 4 from 4 to {FB_WIDTH}"));
+    }
+    Ok(width)
+}
+
+fn parse_height(s: &str) -> Result<usize, String> {
+    let height: usize = s.parse().map_err(|e| format!("{e}"))?;
+    if height == 0 || height > FB_HEIGHT {
+        return Err(format!("must be from 1 to {FB_HEIGHT}"));
+    }
+    Ok(height)
+}
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
@@ -214,27 +628,161 @@ struct Cli {
     disable_depth_buffer: bool,
     #[arg(long, default_value = "CatRoom")]
     scene: String,
+    // at most the framebuffer size, the width a multiple of 4
+    #[arg(long, default_value_t = 640, value_parser = parse_width)]
+    width: usize,
+    #[arg(long, default_value_t = 480, value_parser = parse_height)]
+    height: usize,
+    // write all backend calls of frame --capture-frame to this file
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    #[arg(long, default_value_t = 0)]
+    capture_frame: u64,
+    // render a file written by --capture instead of a scene
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    // the hardware is reset and the frame skipped if a clear, draw or vsync takes longer
+    #[arg(long, default_value_t = 1000)]
+    clear_timeout_ms: u64,
+    #[arg(long, default_value_t = 1000)]
+    draw_timeout_ms: u64,
+    #[arg(long, default_value_t = 1000)]
+    vsync_timeout_ms: u64,
+    // pressing all of these keys together quits, like SIGINT or SIGTERM
+    #[arg(long, value_delimiter = '+', default_value = "ControlLeft+KeyC")]
+    quit_keys: Vec<Key>,
+    // show the previous framebuffer again on exit instead of a black screen
+    #[arg(long)]
+    restore_display: bool,
+    // input devices to use, by name or as cap:keyboard, cap:mouse or cap:gamepad, all
+    // keyboards, mice and gamepads if not given. Devices plugged in later are picked up as well.
+    #[arg(long)]
+    input: Vec<DeviceFilter>,
+    #[arg(long)]
+    input_exclude: Vec<DeviceFilter>,
+    // share the input devices with the console instead of taking them over
+    #[arg(long)]
+    no_grab: bool,
+    // a JSON file with the controls of the scene actions, see rs_common::input::actions
+    #[arg(long)]
+    bindings: Option<PathBuf>,
+    // scales the mouse motion the scenes see
+    #[arg(long, default_value_t = 1.0)]
+    mouse_sensitivity: f64,
+    // for the randomness of the scene, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    // write the input of the session to this file, the model can replay it with --replay-input
+    #[arg(long)]
+    record_input: Option<PathBuf>,
+    // also take input from clients connecting to this address, e.g. 0.0.0.0:7070 for the
+    // model's --send-input
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+}
+
+fn run<B: Backend>(
+    backend: B,
+    input: EvdevConfig,
+    net: Option<NetSource>,
+    session: &Session,
+    record_input: Option<&Path>,
+    quit_keys: &[Key],
+) {
+    let mut input = MergedSource::new().with(EvdevSource::new(input));
+    if let Some(net) = net {
+        input = input.with(net);
+    }
+    let input: Box<dyn InputSource> = match record_input {
+        Some(path) => Box::new(
+            InputRecorder::create(path, session, input)
+                .unwrap_or_else(|e| panic!("{}: {e:?}", path.display())),
+        ),
+        None => Box::new(input),
+    };
+    let runner = Runner::with_session(backend, input, session)
+        .unwrap_or_else(|| panic!("unknown scene {}", session.scene));
+    runner.run(|_, input| {
+        signal::quit_requested() || quit_keys.iter().all(|&key| input.is_key_down(key))
+    });
 }
 
 fn main() {
     let mut cli = Cli::parse();
-    let scene = std::mem::take(&mut cli.scene);
-    let mut context = Context::new(HwBackend::new(Hw::new().unwrap(), cli));
-
-    let mut scene = scene::create(&scene, &mut context, &mut AssetLoader::default())
-        .expect(&format!("unknown scene {}", &scene));
+    let capture = cli.capture.take();
+    let capture_frame = cli.capture_frame;
+    let replay = cli.replay.take();
+    let quit_keys = cli.quit_keys.clone();
+    let record_input = cli.record_input.take();
+    let mut input = EvdevConfig {
+        exclude: std::mem::take(&mut cli.input_exclude),
+        grab: !cli.no_grab,
+        ..EvdevConfig::default()
+    };
+    if !cli.input.is_empty() {
+        input.include = std::mem::take(&mut cli.input);
+    }
+    let mut session = Session::new(&cli.scene, cli.seed.unwrap_or_else(random_seed));
+    session.mouse_sensitivity = cli.mouse_sensitivity;
+    if let Some(path) = cli.bindings.take() {
+        session.bindings =
+            ActionMap::load(&path).unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+    }
+    let net = cli
+        .listen
+        .map(|addr| NetSource::listen(addr).unwrap_or_else(|e| panic!("listening on {addr}: {e}")));
+    signal::install();
+    let mut backend = HwBackend::new(DevMem::new().unwrap(), cli);
+    if let Some(path) = replay {
+        let capture = Capture::load(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        assert_eq!(
+            capture.viewport,
+            backend.viewport(),
+            "the capture was made with a different --width/--height"
+        );
+        render::capture::replay(&mut backend, &capture)
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        backend.present();
+        backend.wait_idle();
+        // no input devices are grabbed, so Ctrl+C in the terminal quits
+        while !signal::quit_requested() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        return;
+    }
+    match capture {
+        Some(path) => run(
+            Recorder::new(backend, capture_frame, path),
+            input,
+            net,
+            &session,
+            record_input.as_deref(),
+            &quit_keys,
+        ),
+        None => run(
+            backend,
+            input,
+            net,
+            &session,
+            record_input.as_deref(),
+            &quit_keys,
+        ),
+    }
+}
 
-    let mut input_source = EvdevSource::new();
-    let mut input_state = InputState::default();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Emulator;
 
-    loop {
-        while let Some(event) = input_source.poll_event() {
-            input_state.update(event.clone());
-            scene.input(event);
+    #[test]
+    fn display_outside_of_vram_is_left_alone() {
+        for fb in [0, 0xdead_beef, MEM_START + 4] {
+            let mut emu = Emulator::new();
+            emu.set_reg(R_DISPLAY_FB, fb);
+            let cli = Cli::parse_from(["driver", "--restore-display"]);
+            let backend = HwBackend::new(emu, cli);
+            assert!(backend.saved_display.is_none() && !backend.blank_display);
         }
-        context.backend_mut().start_frame();
-        scene.render(&mut context);
-        context.backend_mut().render_frame();
-        scene.update(0.01, &input_state);
     }
 }
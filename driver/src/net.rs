@@ -0,0 +1,198 @@
+// Input from other machines, so that the board can be controlled without a keyboard attached.
+// Clients connect over TCP and send one JSON encoded InputEvent per line, e.g.
+// {"KeyDown":"KeyW"}. The model sends the input of its window with --send-input.
+
+use std::{
+    io::{BufRead, BufReader, ErrorKind},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use rs_common::input::{GamepadAxis, InputEvent, InputSource, InputState};
+
+// how often the threads check whether they should stop
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+pub struct NetSource {
+    receiver: Receiver<InputEvent>,
+    stop: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+    addr: SocketAddr,
+}
+
+impl NetSource {
+    pub fn listen(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // accepting doesn't block, so that the stop flag is checked
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let listener = std::thread::spawn(move || accept_clients(listener, sender, thread_stop));
+        Ok(NetSource {
+            receiver,
+            stop,
+            listener: Some(listener),
+            addr,
+        })
+    }
+    // the address clients connect to, useful when listening on port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for NetSource {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+impl InputSource for NetSource {
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+fn accept_clients(listener: TcpListener, sender: Sender<InputEvent>, stop: Arc<AtomicBool>) {
+    let mut clients = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                eprintln!("input client {addr} connected");
+                let client = Client {
+                    addr,
+                    sender: sender.clone(),
+                    stop: stop.clone(),
+                    state: InputState::default(),
+                };
+                clients.push(std::thread::spawn(move || client.run(stream)));
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::sleep(STOP_POLL),
+            Err(err) => {
+                eprintln!("input client error: {err}");
+                std::thread::sleep(STOP_POLL);
+            }
+        }
+        clients.retain(|client: &JoinHandle<()>| !client.is_finished());
+    }
+    for client in clients {
+        let _ = client.join();
+    }
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+struct Client {
+    addr: SocketAddr,
+    sender: Sender<InputEvent>,
+    stop: Arc<AtomicBool>,
+    // what the client is holding, to release it when the client goes away
+    state: InputState,
+}
+
+impl Client {
+    fn read(&mut self, stream: TcpStream) -> std::io::Result<()> {
+        // reads time out, so that the stop flag is checked at least every STOP_POLL
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(STOP_POLL))?;
+        let mut reader = BufReader::new(stream);
+        // a line that timed out halfway is completed by the next read
+        let mut line = String::new();
+        while !self.stop.load(Ordering::Relaxed) {
+            match reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    match serde_json::from_str::<InputEvent>(&line) {
+                        Ok(event) => {
+                            self.state.update(event.clone());
+                            if self.sender.send(event).is_err() {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("input client {}: {err}: {}", self.addr, line.trim())
+                        }
+                    }
+                    line.clear();
+                }
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+    // releases everything that is still held, so that nothing stays stuck
+    fn release_all(&self) {
+        let keys = self.state.iter_down_keys().map(InputEvent::KeyUp);
+        let buttons = self
+            .state
+            .iter_down_buttons()
+            .map(InputEvent::MouseButtonUp);
+        let gamepad_buttons = self
+            .state
+            .iter_down_gamepad_buttons()
+            .map(InputEvent::GamepadButtonUp);
+        let axes = GamepadAxis::ALL
+            .into_iter()
+            .filter(|&axis| self.state.gamepad_axis(axis) != 0.0)
+            .map(|axis| InputEvent::GamepadAxis(axis, 0.0));
+        for event in keys.chain(buttons).chain(gamepad_buttons).chain(axes) {
+            let _ = self.sender.send(event);
+        }
+    }
+    fn run(mut self, stream: TcpStream) {
+        match self.read(stream) {
+            Ok(()) => eprintln!("input client {} disconnected", self.addr),
+            Err(err) => eprintln!("input client error: {}: {err}", self.addr),
+        }
+        self.release_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Instant};
+
+    use rs_common::input::Key;
+
+    use super::*;
+
+    fn next_event(source: &mut NetSource) -> InputEvent {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = source.poll_event() {
+                return event;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no event arrived");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn loopback() {
+        let mut source = NetSource::listen("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(source.local_addr()).unwrap();
+        // the line is split to check that it is put back together
+        stream.write_all(b"{\"KeyDown\":").unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(STOP_POLL * 2);
+        stream.write_all(b"\"KeyW\"}\n").unwrap();
+        assert_eq!(next_event(&mut source), InputEvent::KeyDown(Key::KeyW));
+        // the key is still held when the client goes away
+        drop(stream);
+        assert_eq!(next_event(&mut source), InputEvent::KeyUp(Key::KeyW));
+    }
+}
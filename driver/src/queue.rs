@@ -0,0 +1,126 @@
+// Work for the hardware is queued instead of being executed right away, so that the CPU can go
+// on building the frame, or the next one, while the rasterizer is busy. HwBackend::poll issues
+// the queued ops in order, each one once the previous one has completed.
+
+use std::collections::{HashMap, VecDeque};
+
+use rs_common::render::{BackendTriangle, fixed::HwTriangle};
+
+use crate::hw::*;
+
+const TRIANGLE_SIZE: u32 = size_of::<HwTriangle>() as u32;
+const CHUNK_TRIANGLES: u32 = 4096;
+
+#[derive(Debug)]
+pub enum Op {
+    // pipeline state, it is only changed once everything before has been rasterized
+    SetReg(u32, u32),
+    Clear {
+        addr: u32,
+        stride: u16,
+        width: u16,
+        height: u16,
+        value: u32,
+    },
+    // len triangles at addr
+    Draw {
+        addr: u32,
+        len: u32,
+    },
+    // copies rows of row_len bytes on the CPU
+    Copy {
+        from: u32,
+        from_stride: u32,
+        to: u32,
+        row_len: u32,
+        rows: u32,
+    },
+    StatsStart,
+    StatsStop,
+    // shows the framebuffer from the next vsync on, this ends a frame
+    Present(u32),
+}
+
+#[derive(Default)]
+pub struct Queue {
+    ops: VecDeque<Op>,
+    // the last queued value of each register, so that redundant changes don't split draws
+    regs: HashMap<u32, u32>,
+}
+
+impl Queue {
+    pub fn push(&mut self, op: Op) {
+        self.ops.push_back(op);
+    }
+    pub fn pop(&mut self) -> Option<Op> {
+        self.ops.pop_front()
+    }
+    pub fn set_reg(&mut self, reg: u32, value: u32) {
+        if self.regs.insert(reg, value) != Some(value) {
+            self.push(Op::SetReg(reg, value));
+        }
+    }
+    // drops the queued ops and queues the last value of every register again, so that the
+    // hardware ends up in the requested state even though the ops that set it are lost.
+    pub fn restart(&mut self) {
+        self.ops.clear();
+        for (&reg, &value) in &self.regs {
+            self.ops.push_back(Op::SetReg(reg, value));
+        }
+    }
+    // extends the last draw instead if the triangles directly follow it in memory
+    pub fn draw(&mut self, addr: u32, len: u32) {
+        if let Some(Op::Draw { addr: a, len: l }) = self.ops.back_mut()
+            && *a + *l * TRIANGLE_SIZE == addr
+            && *l + len <= MAX_DRAW_LEN
+        {
+            *l += len;
+        } else {
+            self.push(Op::Draw { addr, len });
+        }
+    }
+}
+
+// the triangles of a frame, written to chunks of vram that are allocated as needed and
+// reused by later frames.
+#[derive(Default)]
+pub struct CommandBuffer {
+    chunks: Vec<u32>,
+    chunk: usize,
+    used: u32,
+}
+
+impl CommandBuffer {
+    // only valid once the draws of the previous frame using this buffer have completed
+    pub fn reset(&mut self) {
+        self.chunk = 0;
+        self.used = 0;
+    }
+    pub fn draw(
+        &mut self,
+        hw: &mut impl Hw,
+        vram_alloc: &mut VramAlloc,
+        queue: &mut Queue,
+        mut triangles: &[BackendTriangle],
+    ) {
+        while !triangles.is_empty() {
+            if self.used == CHUNK_TRIANGLES {
+                self.chunk += 1;
+                self.used = 0;
+            }
+            if self.chunk == self.chunks.len() {
+                let len = (CHUNK_TRIANGLES * TRIANGLE_SIZE) as usize;
+                self.chunks
+                    .push(vram_alloc.alloc(len).expect("out of vram for commands"));
+            }
+            let n = triangles.len().min((CHUNK_TRIANGLES - self.used) as usize);
+            let addr = self.chunks[self.chunk] + self.used * TRIANGLE_SIZE;
+            for (i, t) in triangles[..n].iter().enumerate() {
+                hw.write(addr + i as u32 * TRIANGLE_SIZE, HwTriangle::new(t));
+            }
+            queue.draw(addr, n as u32);
+            self.used += n as u32;
+            triangles = &triangles[n..];
+        }
+    }
+}
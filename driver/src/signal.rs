@@ -0,0 +1,25 @@
+// SIGINT and SIGTERM only ask the main loop to quit, so that the driver can shut down in order.
+// A second signal terminates right away, in case the shutdown itself hangs.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(signal: libc::c_int) {
+    QUIT.store(true, Ordering::Relaxed);
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+pub fn install() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            libc::signal(signal, handle as *const () as libc::sighandler_t);
+        }
+    }
+}
+
+pub fn quit_requested() -> bool {
+    QUIT.load(Ordering::Relaxed)
+}
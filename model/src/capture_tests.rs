@@ -0,0 +1,78 @@
+use std::io::ErrorKind;
+
+use clap::Parser;
+use rs_common::{
+    mesh::Color,
+    render::{
+        AlphaMode, AlphaState, BackendTriangle, DepthMode, DepthState, SamplerState, Texture,
+        TextureType, Triangle4, Viewport, WrapMode,
+        capture::{self, Capture, Command},
+    },
+};
+
+use crate::{Cli, ModelBackend};
+
+// every kind of command survives saving and loading, and replaying checks the ids it is given
+#[test]
+fn capture_round_trip() {
+    let viewport = Viewport::new(320, 240);
+    let triangle = Triangle4::new([
+        [0.0, 0.0, 1.0, 0.0, 0.0],
+        [0.5, 0.0, 1.0, 1.0, 0.0],
+        [0.0, 0.5, 1.0, 0.0, 1.0],
+    ]);
+    let texture = Texture {
+        data: (0..64).collect::<Vec<u8>>().into(),
+        ty: TextureType {
+            width: 4,
+            height: 4,
+            stride: 4,
+        },
+    };
+    let capture = Capture {
+        viewport,
+        commands: vec![
+            Command::LoadTexture(0, texture),
+            Command::CreateRenderTarget(0, 1, Viewport::new(64, 64)),
+            Command::BeginFrame(Color::rgb(1, 2, 3)),
+            Command::SetRenderTarget(Some(0), Color::BLACK),
+            Command::UseTexture(Some(0)),
+            Command::SetSampler(SamplerState {
+                wrap: WrapMode::ClampToBorder,
+                border: Color {
+                    r: 4,
+                    g: 5,
+                    b: 6,
+                    a: 7,
+                },
+            }),
+            Command::SetDepth(DepthState {
+                mode: DepthMode::Le,
+                write: false,
+            }),
+            Command::SetAlpha(AlphaState {
+                mode: AlphaMode::Mask,
+                cutoff: 0.25,
+            }),
+            Command::Draw(vec![BackendTriangle::new(&triangle, viewport).unwrap()]),
+            Command::SetRenderTarget(None, Color::WHITE),
+            Command::UseTexture(None),
+            Command::UnloadTexture(0),
+            Command::EndFrame,
+        ],
+    };
+    let path = std::env::temp_dir().join(format!("capture_{}.rcap", std::process::id()));
+    capture.save(&path).unwrap();
+    let loaded = Capture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(format!("{loaded:?}"), format!("{capture:?}"));
+    let cli = Cli::parse_from(["model", "--width", "320", "--height", "240"]);
+    let mut backend = ModelBackend::new(&cli, None);
+    capture::replay(&mut backend, &loaded).unwrap();
+    let broken = Capture {
+        viewport,
+        commands: vec![Command::UseTexture(Some(5))],
+    };
+    let err = capture::replay(&mut backend, &broken).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
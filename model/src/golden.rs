@@ -0,0 +1,136 @@
+// golden image tests: render fixed frames of the built-in scenes and compare them against the
+// references in tests/golden. Run with UPDATE_GOLDEN=1 to regenerate the references after an
+// intended change. On failure the actual frame and a diff image are written to target/golden.
+
+use std::path::Path;
+
+use clap::Parser;
+use image::{Rgb, RgbImage};
+use rs_common::{
+    assets::AssetLoader,
+    input::NullInputSource,
+    runner::{Runner, Session},
+};
+
+use crate::{Cli, ModelBackend};
+
+// per channel difference that still counts as the same color
+const TOLERANCE: u8 = 2;
+// fraction of pixels allowed to be further off, so that rounding along edges doesn't fail
+const MAX_DIFFERING: f64 = 0.001;
+const ARGS: [&str; 5] = ["model", "--width", "320", "--height", "240"];
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn asset_loader() -> AssetLoader {
+    AssetLoader::new(manifest_dir().join("tests/assets"))
+}
+
+fn render(scene: &str, args: &[&str], frames: &[usize]) -> Vec<RgbImage> {
+    let cli = Cli::parse_from(ARGS.iter().chain(args));
+    let backend = ModelBackend::new(&cli, None);
+    let session = Session::new(scene, 0);
+    let runner = Runner::with_assets(backend, NullInputSource, &session, &mut asset_loader())
+        .unwrap_or_else(|| panic!("unknown scene {scene}"));
+    let mut images = Vec::new();
+    runner.run_frames(frames.iter().max().unwrap() + 1, |backend, i| {
+        if frames.contains(&i) {
+            images.push(backend.frame_image());
+        }
+    });
+    images
+}
+
+fn compare(name: &str, actual: &RgbImage) {
+    let path = manifest_dir().join(format!("tests/golden/{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&path).unwrap();
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "{}: {e}, run with UPDATE_GOLDEN=1 to create it",
+                path.display()
+            )
+        })
+        .into_rgb8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name}: size differs"
+    );
+    let mut diff = RgbImage::new(actual.width(), actual.height());
+    let mut differing = 0;
+    let mut max_delta = 0;
+    let (mut min, mut max) = ([u32::MAX; 2], [0; 2]);
+    for (x, y, a) in actual.enumerate_pixels() {
+        let e = expected.get_pixel(x, y);
+        let delta = (0..3).map(|i| a[i].abs_diff(e[i])).max().unwrap();
+        if delta > TOLERANCE {
+            differing += 1;
+            max_delta = max_delta.max(delta);
+            min = [min[0].min(x), min[1].min(y)];
+            max = [max[0].max(x), max[1].max(y)];
+            diff.put_pixel(x, y, Rgb([255, 0, 0]));
+        } else {
+            // dimmed expected image, so the differences stand out
+            diff.put_pixel(x, y, Rgb(e.0.map(|c| c / 4)));
+        }
+    }
+    let total = actual.width() * actual.height();
+    if differing as f64 > MAX_DIFFERING * total as f64 {
+        let out = manifest_dir().join("target/golden");
+        std::fs::create_dir_all(&out).unwrap();
+        actual.save(out.join(format!("{name}.actual.png"))).unwrap();
+        diff.save(out.join(format!("{name}.diff.png"))).unwrap();
+        panic!(
+            "{name}: {differing} of {total} pixels differ by more than {TOLERANCE} \
+             (at most {max_delta}) in x {}..={} y {}..={}, see {}",
+            min[0],
+            max[0],
+            min[1],
+            max[1],
+            out.display()
+        );
+    }
+}
+
+fn check_scene(name: &str, scene: &str, args: &[&str], frames: &[usize]) {
+    for (image, frame) in render(scene, args, frames).iter().zip(frames) {
+        compare(&format!("{name}_{frame:03}"), image);
+    }
+}
+
+#[test]
+fn cube() {
+    check_scene("cube", "Cube", &[], &[0, 30]);
+}
+
+#[test]
+fn cube_fixed_point() {
+    check_scene("cube_fixed_point", "Cube", &["--fixed-point"], &[0, 30]);
+}
+
+#[test]
+fn cat_room() {
+    check_scene("cat_room", "CatRoom", &[], &[0, 30]);
+}
+
+#[test]
+fn sphere() {
+    check_scene("sphere", "Sphere", &[], &[0, 30]);
+}
+
+#[test]
+fn tetris() {
+    // the first piece is fixed, later ones come from the session seed
+    check_scene("tetris", "Tetris", &[], &[0, 30]);
+}
+
+#[test]
+fn gltf() {
+    check_scene("gltf", "Gltf:scene.gltf", &[], &[0]);
+}
@@ -1,11 +1,38 @@
 #![allow(dead_code)]
 
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    collections::VecDeque,
+    io::Write,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
 use clap::Parser;
+use image::{GrayImage, RgbImage};
 use rs_common::{
-    assets::AssetLoader,
-    render::{Backend, BackendTriangle, Context, Texture, HEIGHT, TILE_SIZE, WIDTH}, scene,
+    input::{ActionMap, InputEvent, InputRecorder, InputReplay, InputSource, NullInputSource},
+    mesh::Color,
+    render::{
+        AlphaMode, AlphaState, Backend, BackendTriangle, DepthState, SamplerState, TILE_SIZE,
+        Texture, TextureType, Viewport,
+        capture::{self, Capture, Recorder},
+        fixed::{self, HwTriangle},
+    },
+    runner::{Runner, Session, random_seed},
 };
-use std::sync::Arc;
+
+#[cfg(test)]
+mod capture_tests;
+#[cfg(test)]
+mod golden;
+#[cfg(test)]
+mod replay_tests;
+mod window_input;
+
+use window_input::WindowEvents;
 
 #[derive(Default, Debug)]
 struct Stats {
@@ -56,18 +83,37 @@ fn coarse_raster(stats: &mut Stats, p: &BackendTriangle) -> Vec<Tile> {
     tiles
 }
 
+// the state fine_raster needs from the current draw call
+struct DrawState<'a> {
+    viewport: Viewport,
+    depth_state: DepthState,
+    alpha_state: AlphaState,
+    texture: Option<&'a Texture<'static>>,
+    sampler: SamplerState,
+}
+
 fn fine_raster(
     stats: &mut Stats,
     p: &BackendTriangle,
     tile: &Tile,
+    state: &DrawState,
     buffer: &mut [u32],
     depth: &mut [f64],
-    texture: Option<&Texture>,
 ) {
+    let DrawState {
+        viewport,
+        depth_state,
+        alpha_state,
+        texture,
+        sampler,
+    } = *state;
     for oy in 0..TILE_SIZE {
         for ox in 0..TILE_SIZE {
             let y = tile.pos[1] * TILE_SIZE + oy;
             let x = tile.pos[0] * TILE_SIZE + ox;
+            if x >= viewport.width || y >= viewport.height {
+                continue;
+            }
             let e = [0, 1, 2].map(|i| {
                 tile.edge_vec[i] + p.edge_mat[0][i] * ox as f64 + p.edge_mat[1][i] * oy as f64
             });
@@ -77,79 +123,333 @@ fn fine_raster(
             }
             stats.inside_pixels += 1;
             let wr = e[0] + e[1] + e[2];
-            let depth_ptr = &mut depth[y * WIDTH + x];
-            if wr <= *depth_ptr {
+            let depth_ptr = &mut depth[y * viewport.width + x];
+            if !depth_state.mode.test(wr, *depth_ptr) {
                 continue;
             }
-            *depth_ptr = wr;
             stats.depth_pass_pixels += 1;
             let u = (0..3).map(|i| p.uv[i][0] * e[i]).sum::<f64>() / wr;
             let v = (0..3).map(|i| p.uv[i][1] * e[i]).sum::<f64>() / wr;
-            let rgb = if let Some(texture) = texture {
-                let tx = ((u * (texture.ty.width as f64)) as usize).clamp(0, texture.ty.width - 1);
-                let ty =
-                    ((v * (texture.ty.height as f64)) as usize).clamp(0, texture.ty.height - 1);
-                let addr = (tx + ty * texture.ty.stride) * 4;
-                let d = &texture.data[addr..addr + 4];
-                [d[0], d[1], d[2]]
+            let rgba = if let Some(texture) = texture {
+                let tx = sampler.texel(
+                    (u * (texture.ty.width as f64)).floor() as i64,
+                    texture.ty.width,
+                );
+                let ty = sampler.texel(
+                    (v * (texture.ty.height as f64)).floor() as i64,
+                    texture.ty.height,
+                );
+                if let (Some(tx), Some(ty)) = (tx, ty) {
+                    let addr = (tx + ty * texture.ty.stride) * 4;
+                    let d = &texture.data[addr..addr + 4];
+                    [d[0], d[1], d[2], d[3]]
+                } else {
+                    let b = sampler.border;
+                    [b.r, b.g, b.b, b.a]
+                }
             } else {
-                [255, 255, 255]
+                [255, 255, 255, 255]
             };
-            buffer[y * WIDTH + x] = rgb[2] as u32 | (rgb[1] as u32) << 8 | (rgb[0] as u32) << 16;
+            // modulated with the vertex color, like the hardware does for rgb
+            let vertex = [0, 8, 16, 24].map(|s| {
+                (0..3)
+                    .map(|i| (p.rgb[i] >> s & 255) as f64 * e[i])
+                    .sum::<f64>()
+                    / wr
+            });
+            let rgba: [f64; 4] = std::array::from_fn(|i| rgba[i] as f64 * vertex[i] / 255.0);
+            let alpha = rgba[3] / 255.0;
+            if alpha_state.mode == AlphaMode::Mask && alpha < alpha_state.cutoff {
+                continue;
+            }
+            if depth_state.write {
+                *depth_ptr = wr;
+            }
+            let pixel = &mut buffer[y * viewport.width + x];
+            let rgb = if alpha_state.mode == AlphaMode::Blend {
+                let dst = [(*pixel >> 16) as u8, (*pixel >> 8) as u8, *pixel as u8];
+                [0, 1, 2].map(|i| (rgba[i] * alpha + dst[i] as f64 * (1.0 - alpha)).round() as u8)
+            } else {
+                [0, 1, 2].map(|i| rgba[i].round() as u8)
+            };
+            *pixel = rgb[2] as u32 | (rgb[1] as u32) << 8 | (rgb[0] as u32) << 16;
+        }
+    }
+}
+
+// rasterizes from the quantized HwTriangle with the hardware's integer pipeline.
+// Like the hardware it ignores the alpha state.
+fn fixed_raster(
+    stats: &mut Stats,
+    t: &HwTriangle,
+    state: &DrawState,
+    buffer: &mut [u32],
+    depth: &mut [f64],
+) {
+    stats.primitives += 1;
+    let viewport = state.viewport;
+    let (min_x, min_y) = (t.min_x as usize, t.min_y as usize);
+    for ty in 0..=t.max_y as usize - min_y {
+        for tx in 0..=t.max_x as usize - min_x {
+            stats.coarse_tiles += 1;
+            if !t.tile_viable(tx, ty) {
+                continue;
+            }
+            stats.fine_tiles += 1;
+            for oy in 0..TILE_SIZE {
+                for ox in 0..TILE_SIZE {
+                    let (px, py) = (tx * TILE_SIZE + ox, ty * TILE_SIZE + oy);
+                    let e = t.edge_fns(px, py);
+                    if e.iter().any(|&e| e < 0) {
+                        continue;
+                    }
+                    let (x, y) = (min_x * TILE_SIZE + px, min_y * TILE_SIZE + py);
+                    if x >= viewport.width || y >= viewport.height {
+                        continue;
+                    }
+                    stats.inside_pixels += 1;
+                    let z = fixed::depth(e);
+                    let depth_ptr = &mut depth[y * viewport.width + x];
+                    if !state.depth_state.mode.test(z, *depth_ptr as i16) {
+                        continue;
+                    }
+                    if state.depth_state.write {
+                        *depth_ptr = z as f64;
+                    }
+                    stats.depth_pass_pixels += 1;
+                    let (uv, rgb) = t.interpolate(e);
+                    let rgb = fixed::shade(uv, rgb, state.texture, state.sampler);
+                    buffer[y * viewport.width + x] =
+                        rgb[2] as u32 | (rgb[1] as u32) << 8 | (rgb[0] as u32) << 16;
+                }
+            }
         }
     }
 }
 
+fn color_to_pixel(color: Color) -> u32 {
+    color.b as u32 | (color.g as u32) << 8 | (color.r as u32) << 16
+}
+
+struct ModelRenderTarget {
+    viewport: Viewport,
+    frame: Vec<u32>,
+    depth: Vec<f64>,
+    texture: usize,
+}
+
 struct ModelBackend {
-    texture: Option<Arc<Texture<'static>>>,
+    viewport: Viewport,
+    // None once unloaded
+    textures: Vec<Option<Texture<'static>>>,
+    render_targets: Vec<ModelRenderTarget>,
+    // while a render target is active its buffers are swapped into frame/depth
+    current_target: Option<usize>,
+    texture: Option<usize>,
+    sampler: SamplerState,
+    depth_state: DepthState,
+    alpha_state: AlphaState,
     frame: Vec<u32>,
     depth: Vec<f64>,
     stats: Stats,
+    show_stats: bool,
+    // rasterize bit-exactly like the hardware, see render::fixed
+    fixed_point: bool,
+    // None when running headless
+    window: Option<Window>,
+    // events from the window, taken by WindowInput
+    input_events: Rc<RefCell<VecDeque<InputEvent>>>,
+    window_events: WindowEvents,
 }
 
 impl ModelBackend {
-    fn new() -> Self {
+    fn new(cli: &Cli, window: Option<Window>) -> Self {
+        let viewport = Viewport::new(cli.width, cli.height);
         ModelBackend {
+            viewport,
+            textures: Vec::new(),
+            render_targets: Vec::new(),
+            current_target: None,
             texture: None,
-            frame: vec![0; WIDTH * HEIGHT],
-            depth: vec![-f64::INFINITY; WIDTH * HEIGHT],
+            sampler: SamplerState::default(),
+            depth_state: DepthState::default(),
+            alpha_state: AlphaState::default(),
+            frame: vec![0; viewport.width * viewport.height],
+            depth: vec![-f64::INFINITY; viewport.width * viewport.height],
             stats: Stats::default(),
+            show_stats: cli.show_stats,
+            fixed_point: cli.fixed_point,
+            window,
+            input_events: Rc::default(),
+            window_events: WindowEvents::default(),
         }
     }
-    fn start_frame(&mut self) {
-        self.frame.fill(0);
-        self.depth.fill(-f64::INFINITY);
-        self.stats = Stats::default();
+    // the input source for the scene, fed with the window's events whenever a frame is shown
+    fn input(&self) -> WindowInput {
+        WindowInput(self.input_events.clone())
+    }
+    fn collect_input(&mut self) {
+        if let Some(window) = &mut self.window {
+            self.window_events
+                .collect(window, &mut self.input_events.borrow_mut());
+        }
+    }
+    // swaps the screen buffers back in and copies the render target into its texture.
+    fn resolve_render_target(&mut self) {
+        if let Some(i) = self.current_target.take() {
+            let rt = &mut self.render_targets[i];
+            std::mem::swap(&mut self.frame, &mut rt.frame);
+            std::mem::swap(&mut self.depth, &mut rt.depth);
+            self.textures[rt.texture].as_mut().unwrap().data = rt
+                .frame
+                .iter()
+                .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8, 255])
+                .collect::<Vec<_>>()
+                .into();
+        }
+    }
+    // the hardware depth buffer holds Int #(16) values and is cleared to 0
+    fn depth_clear(&self) -> f64 {
+        if self.fixed_point {
+            0.0
+        } else {
+            -f64::INFINITY
+        }
+    }
+    fn target_viewport(&self) -> Viewport {
+        self.current_target
+            .map_or(self.viewport, |i| self.render_targets[i].viewport)
+    }
+    fn frame_image(&self) -> RgbImage {
+        let Viewport { width, height } = self.viewport;
+        let rgb = self
+            .frame
+            .iter()
+            .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8]);
+        RgbImage::from_raw(width as u32, height as u32, rgb.collect()).unwrap()
+    }
+    fn save_frame(&self, path: &Path) -> image::ImageResult<()> {
+        self.frame_image().save(path)
+    }
+    // depth is scaled so that the nearest pixel is white and the farthest black,
+    // pixels that were never written stay black.
+    fn save_depth(&self, path: &Path) -> image::ImageResult<()> {
+        let Viewport { width, height } = self.viewport;
+        let clear = self.depth_clear();
+        let written = self.depth.iter().copied().filter(|&d| d != clear);
+        let (min, max) = written.fold((f64::INFINITY, -f64::INFINITY), |(min, max), d| {
+            (min.min(d), max.max(d))
+        });
+        let scale = if max > min { 255.0 / (max - min) } else { 0.0 };
+        let luma = self.depth.iter().map(|&d| {
+            if d == clear {
+                0
+            } else {
+                ((d - min) * scale) as u8
+            }
+        });
+        GrayImage::from_raw(width as u32, height as u32, luma.collect())
+            .unwrap()
+            .save(path)
     }
 }
 
 impl Backend for ModelBackend {
-    type Texture = Arc<Texture<'static>>;
-    type Error = ();
+    type Texture = usize;
+    type RenderTarget = usize;
+    type Error = String;
+
+    fn viewport(&self) -> Viewport {
+        self.viewport
+    }
 
     fn load_texture(&mut self, texture: Texture<'_>) -> Result<Self::Texture, Self::Error> {
-        Ok(Arc::new(Texture {
+        // the float pipeline samples any size, but --fixed-point has to match the hardware
+        if self.fixed_point && !fixed::supports_texture(&texture.ty) {
+            return Err(format!(
+                "{:?} is not supported by the hardware, sizes must be powers of two from 8 to 1024",
+                texture.ty
+            ));
+        }
+        self.textures.push(Some(Texture {
             data: texture.data.into_owned().into(),
             ty: texture.ty,
-        }))
+        }));
+        Ok(self.textures.len() - 1)
+    }
+
+    fn unload_texture(&mut self, texture: Self::Texture) {
+        self.textures[texture] = None;
     }
 
     fn use_texture(&mut self, texture: Option<&Self::Texture>) {
-        self.texture = texture.cloned();
+        self.texture = texture.copied();
+    }
+
+    fn create_render_target(
+        &mut self,
+        viewport: Viewport,
+    ) -> Result<(Self::RenderTarget, Self::Texture), Self::Error> {
+        let size = viewport.width * viewport.height;
+        let texture = self.load_texture(Texture {
+            data: vec![0; size * 4].into(),
+            ty: TextureType {
+                width: viewport.width,
+                height: viewport.height,
+                stride: viewport.width,
+            },
+        })?;
+        self.render_targets.push(ModelRenderTarget {
+            viewport,
+            frame: vec![0; size],
+            depth: vec![-f64::INFINITY; size],
+            texture,
+        });
+        Ok((self.render_targets.len() - 1, texture))
+    }
+
+    fn set_render_target(&mut self, target: Option<&Self::RenderTarget>, clear: Color) {
+        self.resolve_render_target();
+        if let Some(&i) = target {
+            let rt = &mut self.render_targets[i];
+            std::mem::swap(&mut self.frame, &mut rt.frame);
+            std::mem::swap(&mut self.depth, &mut rt.depth);
+            self.current_target = Some(i);
+        }
+        self.frame.fill(color_to_pixel(clear));
+        let depth_clear = self.depth_clear();
+        self.depth.fill(depth_clear);
+    }
+
+    fn set_sampler(&mut self, sampler: SamplerState) {
+        self.sampler = sampler;
+    }
+
+    fn set_depth(&mut self, depth: DepthState) {
+        self.depth_state = depth;
+    }
+
+    fn set_alpha(&mut self, alpha: AlphaState) {
+        self.alpha_state = alpha;
     }
 
     fn draw(&mut self, triangles: &[BackendTriangle]) {
+        let state = DrawState {
+            viewport: self.target_viewport(),
+            depth_state: self.depth_state,
+            alpha_state: self.alpha_state,
+            texture: self.texture.map(|i| self.textures[i].as_ref().unwrap()),
+            sampler: self.sampler,
+        };
         let ctx = &mut self.stats;
         for p in triangles {
+            if self.fixed_point {
+                let t = HwTriangle::new(p);
+                fixed_raster(ctx, &t, &state, &mut self.frame, &mut self.depth);
+                continue;
+            }
             for tile in coarse_raster(ctx, p) {
-                fine_raster(
-                    ctx,
-                    &p,
-                    &tile,
-                    &mut self.frame,
-                    &mut self.depth,
-                    self.texture.as_deref(),
-                );
+                fine_raster(ctx, &p, &tile, &state, &mut self.frame, &mut self.depth);
             }
         }
         /*if show_bbox {
@@ -165,6 +465,36 @@ impl Backend for ModelBackend {
             }
         }*/
     }
+
+    fn begin_frame(&mut self, clear: Color) {
+        self.stats = Stats::default();
+        self.set_render_target(None, clear);
+    }
+
+    fn end_frame(&mut self) {
+        self.resolve_render_target();
+        if self.show_stats {
+            println!("{:?}", self.stats);
+        }
+    }
+
+    fn present(&mut self) {
+        if let Some(window) = &mut self.window {
+            window
+                .update_with_buffer(&self.frame, self.viewport.width, self.viewport.height)
+                .unwrap();
+        }
+        self.collect_input();
+    }
+}
+
+// keyboard and mouse, minifb has no gamepad support
+struct WindowInput(Rc<RefCell<VecDeque<InputEvent>>>);
+
+impl InputSource for WindowInput {
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        self.0.borrow_mut().pop_front()
+    }
 }
 
 #[derive(Parser)]
@@ -174,40 +504,199 @@ struct Cli {
     show_stats: bool,
     #[arg(long)]
     show_bbox: bool,
+    #[arg(long)]
+    fixed_point: bool,
     #[arg(long, default_value = "CatRoom")]
     scene: String,
+    #[arg(long, default_value_t = 640)]
+    width: usize,
+    #[arg(long, default_value_t = 480)]
+    height: usize,
+    // render without a window and write the frames to --output as PNGs
+    #[arg(long)]
+    headless: bool,
+    #[arg(long, default_value_t = 1)]
+    frames: usize,
+    #[arg(long, default_value = ".")]
+    output: PathBuf,
+    // also write the depth buffer of every frame in headless mode
+    #[arg(long)]
+    save_depth: bool,
+    // write all backend calls of frame --capture-frame to this file
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    #[arg(long, default_value_t = 0)]
+    capture_frame: u64,
+    // render a file written by --capture instead of a scene
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    // print the commands of the replayed capture
+    #[arg(long)]
+    dump: bool,
+    // a JSON file with the controls of the scene actions, see rs_common::input::actions
+    #[arg(long)]
+    bindings: Option<PathBuf>,
+    // scales the mouse motion the scenes see
+    #[arg(long, default_value_t = 1.0)]
+    mouse_sensitivity: f64,
+    // for the randomness of the scene, random if not given unless running headless
+    #[arg(long)]
+    seed: Option<u64>,
+    // write the input of the session to this file
+    #[arg(long)]
+    record_input: Option<PathBuf>,
+    // play the session recorded in this file instead of --scene, a headless replay renders at
+    // least as many frames as it takes to replay all input
+    #[arg(long)]
+    replay_input: Option<PathBuf>,
+    // instead of running a scene, send the input of the window to a driver started with
+    // --listen at this address
+    #[arg(long)]
+    send_input: Option<String>,
 }
 
-use minifb::{Key, Window, WindowOptions};
-
-fn main() {
-    let cli = Cli::parse();
+use minifb::{Window, WindowOptions};
 
+fn create_window(cli: &Cli) -> Window {
     let mut window = Window::new(
-        "Test - ESC to exit",
-        WIDTH,
-        HEIGHT,
+        "Test - click for mouse look, ESC to leave it or exit",
+        cli.width,
+        cli.height,
         WindowOptions::default(),
     )
     .unwrap_or_else(|e| {
         panic!("{}", e);
     });
-
     window.set_target_fps(60);
+    window
+}
+
+fn should_quit(window: &Window, events: &WindowEvents) -> bool {
+    !window.is_open() || events.quit_requested()
+}
 
-    let mut context = Context::new(ModelBackend::new());
-    let mut scene = scene::create(&cli.scene, &mut context, &mut AssetLoader::default())
-        .expect(&format!("unknown scene {}", &cli.scene));
+fn save_frame(cli: &Cli, backend: &ModelBackend, i: usize) {
+    let path = cli.output.join(format!("frame_{i:04}.png"));
+    backend.save_frame(&path).unwrap();
+    if cli.save_depth {
+        let path = cli.output.join(format!("depth_{i:04}.png"));
+        backend.save_depth(&path).unwrap();
+    }
+}
+
+fn session(cli: &Cli) -> Session {
+    let seed = match cli.seed {
+        Some(seed) => seed,
+        None if cli.headless => 0,
+        None => random_seed(),
+    };
+    let mut session = Session::new(&cli.scene, seed);
+    session.mouse_sensitivity = cli.mouse_sensitivity;
+    if let Some(path) = &cli.bindings {
+        session.bindings =
+            ActionMap::load(path).unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+    }
+    session
+}
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        context.backend_mut().start_frame();
-        scene.render(&mut context);
-        if cli.show_stats {
-            println!("{:?}", context.backend().stats);
+// B is either the ModelBackend itself or a Recorder wrapping it
+fn run<B: Backend + Borrow<ModelBackend>>(cli: &Cli, backend: B) {
+    let mut frames = cli.frames;
+    let (session, input): (_, Box<dyn InputSource>) = match &cli.replay_input {
+        Some(path) => {
+            let (session, replay) =
+                InputReplay::load(path).unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+            frames = frames.max(replay.ticks() as usize);
+            (session, Box::new(replay))
         }
+        // headless runs get no input, so that they are reproducible
+        None if cli.headless => (session(cli), Box::new(NullInputSource)),
+        None => (session(cli), Box::new(backend.borrow().input())),
+    };
+    let input: Box<dyn InputSource> = match &cli.record_input {
+        Some(path) => Box::new(
+            InputRecorder::create(path, &session, input)
+                .unwrap_or_else(|e| panic!("{}: {e:?}", path.display())),
+        ),
+        None => input,
+    };
+    let runner = Runner::with_session(backend, input, &session)
+        .unwrap_or_else(|| panic!("unknown scene {}", &session.scene));
+    if cli.headless {
+        runner.run_frames(frames, |backend, i| save_frame(cli, backend.borrow(), i));
+    } else {
+        runner.run(|backend, _| {
+            let backend: &ModelBackend = backend.borrow();
+            should_quit(backend.window.as_ref().unwrap(), &backend.window_events)
+        });
+    }
+}
+
+fn replay(cli: &Cli, path: &Path) {
+    let capture = Capture::load(path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    if cli.dump {
+        for command in &capture.commands {
+            println!("{command}");
+        }
+    }
+    assert_eq!(
+        capture.viewport,
+        Viewport::new(cli.width, cli.height),
+        "the capture was made with a different --width/--height"
+    );
+    let window = (!cli.headless).then(|| create_window(cli));
+    let mut backend = ModelBackend::new(cli, window);
+    capture::replay(&mut backend, &capture).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    if cli.headless {
+        save_frame(cli, &backend, 0);
+        return;
+    }
+    // keep showing the frame until the window is closed
+    while !should_quit(backend.window.as_ref().unwrap(), &backend.window_events) {
+        backend.present();
+    }
+}
+
+// forwards the input of the window as JSON lines, see the driver's net module
+fn send_input(cli: &Cli, addr: &str) {
+    let mut stream = TcpStream::connect(addr).unwrap_or_else(|e| panic!("{addr}: {e}"));
+    stream.set_nodelay(true).unwrap();
+    let mut window = create_window(cli);
+    let frame = vec![0; cli.width * cli.height];
+    let mut window_events = WindowEvents::default();
+    let mut events = VecDeque::new();
+    while !should_quit(&window, &window_events) {
         window
-            .update_with_buffer(&context.backend().frame, WIDTH, HEIGHT)
+            .update_with_buffer(&frame, cli.width, cli.height)
             .unwrap();
-        scene.update(10.0 / 60.0, &Default::default());
+        window_events.collect(&mut window, &mut events);
+        for event in events.drain(..) {
+            let mut line = serde_json::to_string(&event).unwrap();
+            line.push('\n');
+            stream
+                .write_all(line.as_bytes())
+                .unwrap_or_else(|e| panic!("{addr}: {e}"));
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Some(addr) = &cli.send_input {
+        send_input(&cli, addr);
+        return;
+    }
+    if cli.headless {
+        std::fs::create_dir_all(&cli.output).unwrap();
+    }
+    if let Some(path) = &cli.replay {
+        replay(&cli, path);
+        return;
+    }
+    let window = (!cli.headless).then(|| create_window(&cli));
+    let backend = ModelBackend::new(&cli, window);
+    match &cli.capture {
+        Some(path) => run(&cli, Recorder::new(backend, cli.capture_frame, path)),
+        None => run(&cli, backend),
     }
 }
@@ -0,0 +1,210 @@
+// turns what happened in the minifb window into InputEvents, for the scene and for
+// --send-input. minifb only reports state, so presses and motion are found by comparing it
+// with the state after the previous window update.
+//
+// Clicking into the window starts mouse look: the cursor is hidden and its motion is sent
+// as RelMouse until Escape ends it again, the click itself isn't passed on. Otherwise the
+// cursor position is sent as AbsMouse, and Escape asks to quit. This isn't a real pointer
+// capture, minifb can neither grab nor warp the pointer, so mouse look stops when the
+// cursor reaches the edge of the screen.
+
+use std::collections::VecDeque;
+
+use minifb::{KeyRepeat, MouseMode, Window};
+use rs_common::input::{InputEvent, Key, MouseButton};
+
+const MOUSE_BUTTONS: [(minifb::MouseButton, MouseButton); 3] = [
+    (minifb::MouseButton::Left, MouseButton::Left),
+    (minifb::MouseButton::Middle, MouseButton::Middle),
+    (minifb::MouseButton::Right, MouseButton::Right),
+];
+
+#[derive(Default)]
+pub struct WindowEvents {
+    mouse_buttons: [bool; 3],
+    // None while the cursor is outside of the window
+    mouse_pos: Option<(f32, f32)>,
+    // the part of the scroll wheel movement that isn't a whole step yet
+    scroll: (f32, f32),
+    captured: bool,
+    // the left button is down from the click that set captured
+    capture_click: bool,
+    quit: bool,
+}
+
+impl WindowEvents {
+    // appends the events since the last call, to be called after each window update
+    pub fn collect(&mut self, window: &mut Window, events: &mut VecDeque<InputEvent>) {
+        for key in window.get_keys_released() {
+            if key == minifb::Key::Escape {
+                continue;
+            }
+            events.extend(map_key(key).map(InputEvent::KeyUp));
+        }
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            if key == minifb::Key::Escape {
+                // the scene doesn't see Escape, it belongs to the window
+                if self.captured {
+                    self.set_captured(window, false);
+                } else {
+                    self.quit = true;
+                }
+                continue;
+            }
+            events.extend(map_key(key).map(InputEvent::KeyDown));
+        }
+        for (i, (button, btn)) in MOUSE_BUTTONS.into_iter().enumerate() {
+            let down = window.get_mouse_down(button);
+            if down == self.mouse_buttons[i] {
+                continue;
+            }
+            self.mouse_buttons[i] = down;
+            if btn == MouseButton::Left {
+                if down && !self.captured && self.mouse_pos.is_some() {
+                    self.set_captured(window, true);
+                    self.capture_click = true;
+                    continue;
+                }
+                if !down && self.capture_click {
+                    self.capture_click = false;
+                    continue;
+                }
+            }
+            events.push_back(if down {
+                InputEvent::MouseButtonDown(btn)
+            } else {
+                InputEvent::MouseButtonUp(btn)
+            });
+        }
+        // mouse look goes on when the cursor leaves the window, up to the edge of the screen
+        let mode = if self.captured {
+            MouseMode::Pass
+        } else {
+            MouseMode::Discard
+        };
+        let pos = window.get_mouse_pos(mode);
+        match (pos, self.mouse_pos) {
+            (Some((x, y)), Some((last_x, last_y))) if self.captured => {
+                let (dx, dy) = ((x - last_x) as i32, (y - last_y) as i32);
+                if (dx, dy) != (0, 0) {
+                    events.push_back(InputEvent::RelMouse(dx, dy));
+                }
+            }
+            (Some((x, y)), last) if !self.captured && last != pos => {
+                events.push_back(InputEvent::AbsMouse(x as i32, y as i32));
+            }
+            _ => {}
+        }
+        self.mouse_pos = pos;
+        // minifb reports fractions of a step on some platforms
+        if let Some((x, y)) = window.get_scroll_wheel() {
+            self.scroll = (self.scroll.0 + x, self.scroll.1 + y);
+            let steps = (self.scroll.0.trunc(), self.scroll.1.trunc());
+            if steps != (0.0, 0.0) {
+                self.scroll = (self.scroll.0 - steps.0, self.scroll.1 - steps.1);
+                events.push_back(InputEvent::Scroll(steps.0 as i32, steps.1 as i32));
+            }
+        }
+    }
+    fn set_captured(&mut self, window: &mut Window, captured: bool) {
+        self.captured = captured;
+        window.set_cursor_visibility(!captured);
+    }
+    // whether Escape was pressed while the mouse wasn't captured
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+fn map_key(key: minifb::Key) -> Option<Key> {
+    use minifb::Key as K;
+    Some(match key {
+        K::Key0 => Key::Digit0,
+        K::Key1 => Key::Digit1,
+        K::Key2 => Key::Digit2,
+        K::Key3 => Key::Digit3,
+        K::Key4 => Key::Digit4,
+        K::Key5 => Key::Digit5,
+        K::Key6 => Key::Digit6,
+        K::Key7 => Key::Digit7,
+        K::Key8 => Key::Digit8,
+        K::Key9 => Key::Digit9,
+        K::A => Key::KeyA,
+        K::B => Key::KeyB,
+        K::C => Key::KeyC,
+        K::D => Key::KeyD,
+        K::E => Key::KeyE,
+        K::F => Key::KeyF,
+        K::G => Key::KeyG,
+        K::H => Key::KeyH,
+        K::I => Key::KeyI,
+        K::J => Key::KeyJ,
+        K::K => Key::KeyK,
+        K::L => Key::KeyL,
+        K::M => Key::KeyM,
+        K::N => Key::KeyN,
+        K::O => Key::KeyO,
+        K::P => Key::KeyP,
+        K::Q => Key::KeyQ,
+        K::R => Key::KeyR,
+        K::S => Key::KeyS,
+        K::T => Key::KeyT,
+        K::U => Key::KeyU,
+        K::V => Key::KeyV,
+        K::W => Key::KeyW,
+        K::X => Key::KeyX,
+        K::Y => Key::KeyY,
+        K::Z => Key::KeyZ,
+        K::F1 => Key::F1,
+        K::F2 => Key::F2,
+        K::F3 => Key::F3,
+        K::F4 => Key::F4,
+        K::F5 => Key::F5,
+        K::F6 => Key::F6,
+        K::F7 => Key::F7,
+        K::F8 => Key::F8,
+        K::F9 => Key::F9,
+        K::F10 => Key::F10,
+        K::F11 => Key::F11,
+        K::F12 => Key::F12,
+        K::Down => Key::ArrowDown,
+        K::Left => Key::ArrowLeft,
+        K::Right => Key::ArrowRight,
+        K::Up => Key::ArrowUp,
+        K::Apostrophe => Key::Quote,
+        K::Backquote => Key::Backquote,
+        K::Backslash => Key::Backslash,
+        K::Comma => Key::Comma,
+        K::Equal => Key::Equal,
+        K::LeftBracket => Key::BracketLeft,
+        K::Minus => Key::Minus,
+        K::Period => Key::Period,
+        K::RightBracket => Key::BracketRight,
+        K::Semicolon => Key::Semicolon,
+        K::Slash => Key::Slash,
+        K::Backspace => Key::Backspace,
+        K::Delete => Key::Delete,
+        K::End => Key::End,
+        K::Enter => Key::Enter,
+        K::Escape => Key::Escape,
+        K::Home => Key::Home,
+        K::Insert => Key::Insert,
+        K::Menu => Key::ContextMenu,
+        K::PageDown => Key::PageDown,
+        K::PageUp => Key::PageUp,
+        K::Pause => Key::Pause,
+        K::Space => Key::Space,
+        K::Tab => Key::Tab,
+        K::CapsLock => Key::CapsLock,
+        K::ScrollLock => Key::ScrollLock,
+        K::LeftShift => Key::ShiftLeft,
+        K::RightShift => Key::ShiftRight,
+        K::LeftCtrl => Key::ControlLeft,
+        K::RightCtrl => Key::ControlRight,
+        K::LeftAlt => Key::AltLeft,
+        K::RightAlt => Key::AltRight,
+        K::LeftSuper => Key::MetaLeft,
+        K::RightSuper => Key::MetaRight,
+        _ => return None,
+    })
+}
@@ -17,17 +17,25 @@ pub fn resolve_path(name: impl AsRef<Path>, parent: Option<impl AsRef<Path>>) ->
 }
 
 #[derive(Default)]
-pub struct AssetLoader {}
+pub struct AssetLoader {
+    // asset paths are relative to this, the default is the working directory
+    root: PathBuf,
+}
 
 impl AssetLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        AssetLoader { root: root.into() }
+    }
     pub fn open_file(&self, path: impl AsRef<Path>) -> Result<std::fs::File, AssetLoaderError> {
-        Ok(std::fs::File::open(path)?)
+        Ok(std::fs::File::open(self.root.join(path))?)
     }
     pub fn open_file_relative(
         &self,
         path: impl AsRef<Path>,
         parent: Option<impl AsRef<Path>>,
     ) -> Result<std::fs::File, AssetLoaderError> {
-        Ok(std::fs::File::open(resolve_path(path, parent))?)
+        Ok(std::fs::File::open(
+            self.root.join(resolve_path(path, parent)),
+        )?)
     }
 }
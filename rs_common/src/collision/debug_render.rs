@@ -1,9 +1,8 @@
 use std::f64::consts::PI;
 
 use crate::{
-    geometry::{Matrix, Vec2, Vec3, Vec4},
-    mesh::Color,
-    render::{Backend, Context, Triangle4},
+    geometry::{Matrix, Vec3, Vec4},
+    render::{Attributes, Backend, Context, Triangle4},
 };
 
 use super::CapsuleCollider;
@@ -34,8 +33,7 @@ impl CapsuleCollider {
             v.push(
                 Triangle4 {
                     vertices: [a, b, c].map(Vec4::from),
-                    uv: [Vec2::default(); 3],
-                    color: [Color::WHITE; 3],
+                    attributes: [Attributes::default(); 3],
                 }
                 .lighting(0.5, 0.5, [0.707, 0.0, -0.707].into())
                 .transform(view),
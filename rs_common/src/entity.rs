@@ -9,10 +9,10 @@ use itertools::Itertools;
 use crate::{
     assets::AssetLoader,
     collision::{Bvh, CapsuleCollider},
-    geometry::{Matrix, Quaternion, Vec3},
+    geometry::{Matrix, Quaternion, Vec3, Vec4},
     gltf,
-    mesh::Mesh,
-    render::{Backend, Context, HEIGHT, WIDTH},
+    mesh::{Material, Mesh},
+    render::{Backend, Context, Lighting, Viewport},
 };
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -242,9 +242,8 @@ impl Component for Camera {
 }
 
 impl Camera {
-    pub fn view_matrix(&self, transform: &Transform) -> Matrix {
-        Matrix::projection(self.fov_angle, WIDTH as f64, HEIGHT as f64, 0.1, 100.0)
-            * transform.local_to_world.inverse_3x4()
+    pub fn view_matrix(&self, transform: &Transform, viewport: Viewport) -> Matrix {
+        viewport.projection(self.fov_angle, 0.1, 100.0) * transform.local_to_world.inverse_3x4()
     }
 }
 
@@ -255,21 +254,41 @@ impl World {
         }
     }
     pub fn render<B: Backend>(&self, context: &mut Context<B>, camera: EntityId) {
-        let view = self.get::<Camera>(camera).view_matrix(self.get(camera));
+        let view = self
+            .get::<Camera>(camera)
+            .view_matrix(self.get(camera), context.viewport());
+        let lighting = Lighting {
+            ambient: 0.5,
+            diffuse: 0.5,
+            direction: [0.707, 0.0, -0.707].into(),
+        };
+        let draw = |context: &mut Context<B>, mesh: &Mesh, material: &Material, range, world| {
+            context
+                .draw()
+                .opt_textured(material.texture_id())
+                .sampler(material.sampler)
+                .alpha(material.alpha)
+                .depth_write(!material.alpha.is_transparent())
+                .lighting(lighting)
+                .run_indexed(mesh.buffer_id(), range, world, view);
+        };
+        let mut transparent = Vec::new();
         for (_, transform, mesh) in self.iter2::<Transform, Rc<Mesh>>() {
             for (material, idx_range) in &mesh.material_ranges {
-                let v = idx_range
-                    .clone()
-                    .map(|i| {
-                        mesh.triangle4(i)
-                            .transform(transform.local_to_world)
-                            .lighting(0.5, 0.5, [0.707, 0.0, -0.707].into())
-                            .transform(view)
-                    })
-                    .collect::<Vec<_>>();
-                context.draw().opt_textured(material.texture_id()).run(&v);
+                let world = transform.local_to_world;
+                if material.alpha.is_transparent() {
+                    let distance = (view * Vec4::from(world * Vec3::default())).w;
+                    transparent.push((distance, world, mesh, material, idx_range));
+                } else {
+                    draw(context, mesh, material, idx_range.clone(), world);
+                }
             }
         }
+        // blended draws go after everything opaque, back to front
+        transparent.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, world, mesh, material, idx_range) in transparent {
+            draw(context, mesh, material, idx_range.clone(), world);
+        }
     }
     pub fn update_transforms(&mut self) {
         let update_transform = |t: &mut Transform, parent_transform: gltf::Transform| {
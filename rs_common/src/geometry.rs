@@ -25,7 +25,7 @@ pub struct Vec4 {
     pub w: f64,
 }
 #[repr(transparent)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Matrix(pub [[f64; 4]; 4]);
 
 #[repr(transparent)]
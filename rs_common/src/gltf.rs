@@ -3,6 +3,7 @@ use crate::{
     entity::{self, EntityId, World},
     geometry::{Matrix, Quaternion, Vec2, Vec3, Vec4},
     mesh::{self, Color, Texture, TextureState},
+    render::{AlphaMode, AlphaState, SamplerState, WrapMode},
 };
 use binary::Accessor;
 use itertools::Itertools;
@@ -26,6 +27,8 @@ pub use json::AnimationId;
 pub struct Material {
     id: Option<json::MaterialId>,
     texture: Option<Rc<Texture>>,
+    sampler: SamplerState,
+    alpha: AlphaState,
     texcoord_idx: usize,
     color: Color,
 }
@@ -34,6 +37,8 @@ thread_local! {
     static DEFAULT_MATERIAL: Rc<Material> = Rc::new(Material {
         id: None,
         texture: None,
+        sampler: SamplerState::default(),
+        alpha: AlphaState::default(),
         color: Color::WHITE,
         texcoord_idx: 0,
     });
@@ -86,6 +91,8 @@ pub struct GltfImporter<'a> {
     nodes: Memoize<json::NodeId, Rc<Node>>,
     animations: Memoize<json::AnimationId, Rc<Animation>>,
     skins: Memoize<json::SkinId, Rc<Skin>>,
+    // unsupported samplers are reported once per file
+    warned_sampler: Cell<bool>,
 }
 
 #[derive(Error, Debug)]
@@ -170,6 +177,7 @@ impl<'a> GltfImporter<'a> {
             nodes: Default::default(),
             animations: Default::default(),
             skins: Default::default(),
+            warned_sampler: Cell::new(false),
         }
     }
     fn read_chunk(
@@ -373,6 +381,36 @@ impl<'a> GltfImporter<'a> {
             }
         })
     }
+    fn sampler(&self, id: json::TextureId) -> Result<SamplerState, Error> {
+        let texture = self.json.texture(id)?;
+        let Some(sampler_id) = texture.sampler else {
+            return Ok(SamplerState::default());
+        };
+        let sampler = self.json.sampler(sampler_id)?;
+        let wrap = |mode| match mode {
+            json::WrapMode::Repeat => Some(WrapMode::Wrap),
+            json::WrapMode::ClampToEdge => Some(WrapMode::ClampToEdge),
+            json::WrapMode::MirroredRepeat => None,
+        };
+        // the hardware has a single wrap mode for both axes and can't mirror, such samplers
+        // fall back to the mode of the s axis, or Wrap for mirroring
+        let (s, t) = (wrap(sampler.wrap_s), wrap(sampler.wrap_t));
+        if (s.is_none() || s != t) && !self.warned_sampler.replace(true) {
+            eprintln!(
+                "{}: unsupported sampler wrap modes {:?}/{:?}, using {:?}",
+                self.file_name.as_deref().unwrap_or("gltf"),
+                sampler.wrap_s,
+                sampler.wrap_t,
+                s.unwrap_or(WrapMode::Wrap)
+            );
+        }
+        // glTF has no border color, so ClampToBorder never comes from a file and the border
+        // stays at its default
+        Ok(SamplerState {
+            wrap: s.unwrap_or(WrapMode::Wrap),
+            ..Default::default()
+        })
+    }
     fn material(&self, id: json::MaterialId) -> Result<Rc<Material>, Error> {
         self.materials.get_or_insert(id, || {
             let material = self.json.material(id)?;
@@ -382,7 +420,20 @@ impl<'a> GltfImporter<'a> {
                 .as_ref()
                 .map(|info| self.texture(info.index))
                 .transpose()?;
+            let sampler = material
+                .pbr_metallic_roughness
+                .base_color_texture
+                .as_ref()
+                .map_or(Ok(SamplerState::default()), |info| self.sampler(info.index))?;
             let color = material.pbr_metallic_roughness.base_color_factor.into();
+            let alpha = AlphaState {
+                mode: match material.alpha_mode {
+                    json::AlphaMode::OPAQUE => AlphaMode::Opaque,
+                    json::AlphaMode::MASK => AlphaMode::Mask,
+                    json::AlphaMode::BLEND => AlphaMode::Blend,
+                },
+                cutoff: material.alpha_cutoff,
+            };
             Ok(Rc::new(Material {
                 id: Some(id),
                 texcoord_idx: material
@@ -391,6 +442,8 @@ impl<'a> GltfImporter<'a> {
                     .as_ref()
                     .map_or(0, |t| t.tex_coord),
                 texture,
+                sampler,
+                alpha,
                 color,
             }))
         })
@@ -537,6 +590,8 @@ fn translate_material(
         .or_insert_with(|| {
             Rc::new(mesh::Material {
                 texture: material.texture.clone(),
+                sampler: material.sampler,
+                alpha: material.alpha,
             })
         })
         .clone()
@@ -296,8 +296,10 @@ pub struct Texture {
 pub struct Sampler {
     pub mag_filter: Option<u32>,
     pub min_filter: Option<u32>,
-    pub wrap_s: Option<u32>,
-    pub wrap_t: Option<u32>,
+    #[serde(default)]
+    pub wrap_s: WrapMode,
+    #[serde(default)]
+    pub wrap_t: WrapMode,
     pub name: Option<String>,
     pub extras: Extras,
     pub extensions: Extensions,
@@ -314,6 +316,15 @@ pub struct Image {
     pub extensions: Extensions,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(u16)]
+pub enum WrapMode {
+    ClampToEdge = 33071,
+    MirroredRepeat = 33648,
+    #[default]
+    Repeat = 10497,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(u16)]
 pub enum ComponentType {
@@ -1,6 +1,15 @@
+use std::ops::DerefMut;
+
 use bitvec::prelude::*;
+use serde::{Deserialize, Serialize};
+
+mod actions;
+mod record;
+
+pub use actions::*;
+pub use record::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum InputEvent {
     KeyDown(Key),
     KeyUp(Key),
@@ -8,19 +17,137 @@ pub enum InputEvent {
     AbsMouse(i32, i32),
     MouseButtonDown(MouseButton),
     MouseButtonUp(MouseButton),
+    // wheel steps, positive y scrolls up and positive x to the right
+    Scroll(i32, i32),
+    GamepadButtonDown(GamepadButton),
+    GamepadButtonUp(GamepadButton),
+    // sticks go from -1 to 1 with right and down positive, triggers from 0 to 1
+    GamepadAxis(GamepadAxis, f64),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MouseButton {
     Left = 0,
     Middle = 1,
     Right = 2,
 }
 
+impl MouseButton {
+    pub const ALL: [MouseButton; 3] = [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+}
+
+// named after their position, South is A on Xbox and Cross on PlayStation controllers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    LeftStick,
+    RightStick,
+    Select,
+    Start,
+    Mode,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 15] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::North,
+        GamepadButton::West,
+        GamepadButton::LeftShoulder,
+        GamepadButton::RightShoulder,
+        GamepadButton::LeftStick,
+        GamepadButton::RightStick,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::Mode,
+        GamepadButton::DpadUp,
+        GamepadButton::DpadDown,
+        GamepadButton::DpadLeft,
+        GamepadButton::DpadRight,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const ALL: [GamepadAxis; 6] = [
+        GamepadAxis::LeftX,
+        GamepadAxis::LeftY,
+        GamepadAxis::RightX,
+        GamepadAxis::RightY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+}
+
+// axis values closer to rest than this read as 0, worn sticks rarely return to exactly 0
+pub const STICK_DEADZONE: f64 = 0.15;
+pub const TRIGGER_DEADZONE: f64 = 0.05;
+
+#[allow(unused_variables)]
 pub trait InputSource {
+    // called by the runner before it polls, with the number of scene updates so far. Events
+    // polled afterwards are seen by update number tick.
+    fn set_tick(&mut self, tick: u64) {}
     fn poll_event(&mut self) -> Option<InputEvent>;
 }
 
+impl<S: InputSource + ?Sized> InputSource for Box<S> {
+    fn set_tick(&mut self, tick: u64) {
+        (**self).set_tick(tick);
+    }
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        (**self).poll_event()
+    }
+}
+
+// the events of several sources, e.g. local devices and a remote one
+#[derive(Default)]
+pub struct MergedSource {
+    sources: Vec<Box<dyn InputSource>>,
+}
+
+impl MergedSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with(mut self, source: impl InputSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+}
+
+impl InputSource for MergedSource {
+    fn set_tick(&mut self, tick: u64) {
+        for source in &mut self.sources {
+            source.set_tick(tick);
+        }
+    }
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        self.sources
+            .iter_mut()
+            .find_map(|source| source.poll_event())
+    }
+}
+
 pub struct NullInputSource;
 
 impl InputSource for NullInputSource {
@@ -29,47 +156,117 @@ impl InputSource for NullInputSource {
     }
 }
 
+// which buttons of one kind are down, and which went down or up during the current tick.
+// A button can be both pressed and released if it was tapped quickly.
 #[derive(Default, Clone)]
+struct Buttons<B> {
+    down: B,
+    pressed: B,
+    released: B,
+}
+
+impl<B: DerefMut<Target = BitSlice>> Buttons<B> {
+    fn set(&mut self, i: usize, down: bool) {
+        if self.down[i] != down {
+            self.down.set(i, down);
+            if down {
+                self.pressed.set(i, true);
+            } else {
+                self.released.set(i, true);
+            }
+        }
+    }
+    fn next_tick(&mut self) {
+        self.pressed.fill(false);
+        self.released.fill(false);
+    }
+}
+
+#[derive(Clone)]
 pub struct InputState {
-    active_keys: BitArr!(for 256),
-    mouse_buttons: BitArr!(for 3),
+    keys: Buttons<BitArr!(for 256)>,
+    mouse_buttons: Buttons<BitArr!(for 3)>,
+    // the last absolute position, relative motion only goes into mouse_delta
     mouse_pos: [i32; 2],
+    // relative motion and wheel steps during the current tick
+    mouse_delta: [i32; 2],
+    scroll: [i32; 2],
+    mouse_sensitivity: f64,
+    gamepad_buttons: Buttons<BitArr!(for 16)>,
+    gamepad_axes: [f64; 6],
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        InputState {
+            keys: Buttons::default(),
+            mouse_buttons: Buttons::default(),
+            mouse_pos: [0; 2],
+            mouse_delta: [0; 2],
+            scroll: [0; 2],
+            mouse_sensitivity: 1.0,
+            gamepad_buttons: Buttons::default(),
+            gamepad_axes: [0.0; 6],
+        }
+    }
 }
 
 impl InputState {
     pub fn update(&mut self, event: InputEvent) {
         match event {
-            InputEvent::KeyDown(key) => {
-                self.active_keys.set(key as usize, true);
-            }
-            InputEvent::KeyUp(key) => {
-                self.active_keys.set(key as usize, false);
-            }
-            InputEvent::MouseButtonDown(btn) => {
-                self.mouse_buttons.set(btn as usize, true);
-            }
-            InputEvent::MouseButtonUp(btn) => {
-                self.mouse_buttons.set(btn as usize, false);
-            }
+            InputEvent::KeyDown(key) => self.keys.set(key as usize, true),
+            InputEvent::KeyUp(key) => self.keys.set(key as usize, false),
+            InputEvent::MouseButtonDown(btn) => self.mouse_buttons.set(btn as usize, true),
+            InputEvent::MouseButtonUp(btn) => self.mouse_buttons.set(btn as usize, false),
             InputEvent::AbsMouse(x, y) => self.mouse_pos = [x, y],
             InputEvent::RelMouse(x, y) => {
-                self.mouse_pos = [self.mouse_pos[0] + x, self.mouse_pos[1] + y]
+                self.mouse_delta = [self.mouse_delta[0] + x, self.mouse_delta[1] + y]
             }
+            InputEvent::Scroll(x, y) => self.scroll = [self.scroll[0] + x, self.scroll[1] + y],
+            InputEvent::GamepadButtonDown(btn) => self.gamepad_buttons.set(btn as usize, true),
+            InputEvent::GamepadButtonUp(btn) => self.gamepad_buttons.set(btn as usize, false),
+            InputEvent::GamepadAxis(axis, value) => self.gamepad_axes[axis as usize] = value,
         }
     }
+    // forgets the presses, releases and motion of the tick that was just simulated, the
+    // runner calls this after each scene update.
+    pub fn next_tick(&mut self) {
+        self.keys.next_tick();
+        self.mouse_buttons.next_tick();
+        self.gamepad_buttons.next_tick();
+        self.mouse_delta = [0; 2];
+        self.scroll = [0; 2];
+    }
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f64) {
+        self.mouse_sensitivity = sensitivity;
+    }
     pub fn is_key_down(&self, key: Key) -> bool {
-        *self.active_keys.get(key as usize).unwrap()
+        self.keys.down[key as usize]
+    }
+    pub fn was_key_pressed(&self, key: Key) -> bool {
+        self.keys.pressed[key as usize]
+    }
+    pub fn was_key_released(&self, key: Key) -> bool {
+        self.keys.released[key as usize]
     }
     pub fn iter_down_keys(&self) -> impl Iterator<Item = Key> {
-        self.active_keys
+        self.keys
+            .down
             .iter_ones()
             .map(|code| unsafe { std::mem::transmute::<u8, Key>(code as u8) })
     }
     pub fn is_button_down(&self, btn: MouseButton) -> bool {
-        *self.mouse_buttons.get(btn as usize).unwrap()
+        self.mouse_buttons.down[btn as usize]
+    }
+    pub fn was_button_pressed(&self, btn: MouseButton) -> bool {
+        self.mouse_buttons.pressed[btn as usize]
+    }
+    pub fn was_button_released(&self, btn: MouseButton) -> bool {
+        self.mouse_buttons.released[btn as usize]
     }
     pub fn iter_down_buttons(&self) -> impl Iterator<Item = MouseButton> {
         self.mouse_buttons
+            .down
             .iter_ones()
             .map(|code| unsafe { std::mem::transmute::<u8, MouseButton>(code as u8) })
     }
@@ -79,6 +276,45 @@ impl InputState {
     pub fn mouse_y(&self) -> i32 {
         self.mouse_pos[1]
     }
+    // how far the mouse moved during this tick, scaled by the sensitivity
+    pub fn mouse_delta(&self) -> [f64; 2] {
+        self.mouse_delta.map(|d| d as f64 * self.mouse_sensitivity)
+    }
+    // the wheel steps scrolled during this tick
+    pub fn scroll_x(&self) -> i32 {
+        self.scroll[0]
+    }
+    pub fn scroll_y(&self) -> i32 {
+        self.scroll[1]
+    }
+    pub fn is_gamepad_button_down(&self, btn: GamepadButton) -> bool {
+        self.gamepad_buttons.down[btn as usize]
+    }
+    pub fn was_gamepad_button_pressed(&self, btn: GamepadButton) -> bool {
+        self.gamepad_buttons.pressed[btn as usize]
+    }
+    pub fn was_gamepad_button_released(&self, btn: GamepadButton) -> bool {
+        self.gamepad_buttons.released[btn as usize]
+    }
+    pub fn iter_down_gamepad_buttons(&self) -> impl Iterator<Item = GamepadButton> {
+        self.gamepad_buttons
+            .down
+            .iter_ones()
+            .map(|code| unsafe { std::mem::transmute::<u8, GamepadButton>(code as u8) })
+    }
+    // with the deadzone cut out, so that values still start at 0 just outside of it
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f64 {
+        let value = self.gamepad_axes[axis as usize];
+        let deadzone = match axis {
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => TRIGGER_DEADZONE,
+            _ => STICK_DEADZONE,
+        };
+        if value.abs() <= deadzone {
+            0.0
+        } else {
+            value.signum() * (value.abs() - deadzone) / (1.0 - deadzone)
+        }
+    }
 }
 
 impl std::fmt::Debug for InputState {
@@ -86,15 +322,22 @@ impl std::fmt::Debug for InputState {
         f.debug_struct("InputState")
             .field("active_keys", &self.iter_down_keys().collect::<Vec<_>>())
             .field("mouse_pos", &self.mouse_pos)
+            .field("mouse_delta", &self.mouse_delta)
             .field(
                 "mouse_buttons",
                 &self.iter_down_buttons().collect::<Vec<_>>(),
             )
+            .field("scroll", &self.scroll)
+            .field(
+                "gamepad_buttons",
+                &self.iter_down_gamepad_buttons().collect::<Vec<_>>(),
+            )
+            .field("gamepad_axes", &self.gamepad_axes)
             .finish()
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Key {
     Backquote,
@@ -191,3 +434,58 @@ pub enum Key {
     ScrollLock,
     Pause,
 }
+
+// parses the variant names, e.g. "KeyQ" or "ControlLeft"
+impl std::str::FromStr for Key {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=Key::Pause as u8)
+            .map(|code| unsafe { std::mem::transmute::<u8, Key>(code) })
+            .find(|key| format!("{key:?}") == s)
+            .ok_or_else(|| format!("unknown key {s}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_last_one_tick() {
+        let mut state = InputState::default();
+        state.update(InputEvent::KeyDown(Key::KeyA));
+        assert!(state.is_key_down(Key::KeyA) && state.was_key_pressed(Key::KeyA));
+        // held, and key repeat doesn't press it again
+        state.next_tick();
+        state.update(InputEvent::KeyDown(Key::KeyA));
+        assert!(state.is_key_down(Key::KeyA) && !state.was_key_pressed(Key::KeyA));
+        state.update(InputEvent::KeyUp(Key::KeyA));
+        assert!(!state.is_key_down(Key::KeyA) && state.was_key_released(Key::KeyA));
+        state.next_tick();
+        assert!(!state.was_key_released(Key::KeyA));
+    }
+
+    #[test]
+    fn tap_is_pressed_and_released() {
+        let mut state = InputState::default();
+        state.update(InputEvent::MouseButtonDown(MouseButton::Left));
+        state.update(InputEvent::MouseButtonUp(MouseButton::Left));
+        assert!(!state.is_button_down(MouseButton::Left));
+        assert!(state.was_button_pressed(MouseButton::Left));
+        assert!(state.was_button_released(MouseButton::Left));
+        state.next_tick();
+        assert!(!state.was_button_pressed(MouseButton::Left));
+        assert!(!state.was_button_released(MouseButton::Left));
+    }
+
+    #[test]
+    fn motion_lasts_one_tick() {
+        let mut state = InputState::default();
+        state.set_mouse_sensitivity(0.5);
+        state.update(InputEvent::RelMouse(4, 0));
+        state.update(InputEvent::RelMouse(2, -6));
+        assert_eq!(state.mouse_delta(), [3.0, -3.0]);
+        state.next_tick();
+        assert_eq!(state.mouse_delta(), [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,406 @@
+// Scenes ask for named actions instead of raw keys, so that the controls can be changed in a
+// config file or at runtime without touching the scenes. The config is JSON, e.g.
+//
+//   {
+//     "jump": { "button": ["Space", "gamepad:South"] },
+//     "move": { "axis2": {
+//       "x": { "positive": ["KeyD", "gamepad:LeftX"], "negative": ["KeyA"] },
+//       "y": { "positive": ["KeyW"], "negative": ["KeyS", "gamepad:LeftY"] }
+//     } }
+//   }
+//
+// Controls are key names, mouse:<button>, gamepad:<button> or gamepad:<axis>.
+
+use std::{collections::BTreeMap, fmt, ops::Deref, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::*;
+
+// buttons count as pressed and axes as active beyond this
+const PRESS_THRESHOLD: f64 = 0.5;
+
+#[derive(Error, Debug)]
+pub enum ActionMapError {
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+    #[error("json error")]
+    JsonError(#[from] serde_json::Error),
+}
+
+// a single key, button or axis that can be bound to an action
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Control {
+    Key(Key),
+    MouseButton(MouseButton),
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis),
+}
+
+impl Control {
+    // buttons are 0 or 1, axes are passed through with their sign
+    fn value(&self, state: &InputState) -> f64 {
+        match *self {
+            Control::Key(key) => state.is_key_down(key) as u32 as f64,
+            Control::MouseButton(btn) => state.is_button_down(btn) as u32 as f64,
+            Control::GamepadButton(btn) => state.is_gamepad_button_down(btn) as u32 as f64,
+            Control::GamepadAxis(axis) => state.gamepad_axis(axis),
+        }
+    }
+    // for buttons tapped so quickly that they were never down when a scene looked
+    fn was_pressed(&self, state: &InputState) -> bool {
+        match *self {
+            Control::Key(key) => state.was_key_pressed(key),
+            Control::MouseButton(btn) => state.was_button_pressed(btn),
+            Control::GamepadButton(btn) => state.was_gamepad_button_pressed(btn),
+            Control::GamepadAxis(_) => false,
+        }
+    }
+    fn was_released(&self, state: &InputState) -> bool {
+        match *self {
+            Control::Key(key) => state.was_key_released(key),
+            Control::MouseButton(btn) => state.was_button_released(btn),
+            Control::GamepadButton(btn) => state.was_gamepad_button_released(btn),
+            Control::GamepadAxis(_) => false,
+        }
+    }
+    // the control that was pressed or moved, for binding whatever the player uses next
+    pub fn from_event(event: &InputEvent) -> Option<Control> {
+        match *event {
+            InputEvent::KeyDown(key) => Some(Control::Key(key)),
+            InputEvent::MouseButtonDown(btn) => Some(Control::MouseButton(btn)),
+            InputEvent::GamepadButtonDown(btn) => Some(Control::GamepadButton(btn)),
+            InputEvent::GamepadAxis(axis, value) if value.abs() > PRESS_THRESHOLD => {
+                Some(Control::GamepadAxis(axis))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn by_name<T: fmt::Debug + Copy>(all: &[T], name: &str) -> Option<T> {
+    all.iter().copied().find(|v| format!("{v:?}") == name)
+}
+
+impl FromStr for Control {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let control = match s.split_once(':') {
+            None => Key::from_str(s).ok().map(Control::Key),
+            Some(("mouse", name)) => by_name(&MouseButton::ALL, name).map(Control::MouseButton),
+            Some(("gamepad", name)) => by_name(&GamepadButton::ALL, name)
+                .map(Control::GamepadButton)
+                .or_else(|| by_name(&GamepadAxis::ALL, name).map(Control::GamepadAxis)),
+            Some(_) => None,
+        };
+        control.ok_or_else(|| format!("unknown control {s}"))
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Control::Key(key) => write!(f, "{key:?}"),
+            Control::MouseButton(btn) => write!(f, "mouse:{btn:?}"),
+            Control::GamepadButton(btn) => write!(f, "gamepad:{btn:?}"),
+            Control::GamepadAxis(axis) => write!(f, "gamepad:{axis:?}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Control {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Control> for String {
+    fn from(control: Control) -> Self {
+        control.to_string()
+    }
+}
+
+// the controls on the positive side add to the axis, the ones on the negative side subtract
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AxisBinding {
+    #[serde(default)]
+    pub positive: Vec<Control>,
+    #[serde(default)]
+    pub negative: Vec<Control>,
+}
+
+impl AxisBinding {
+    fn value(&self, state: &InputState) -> f64 {
+        let sum = |controls: &[Control]| controls.iter().map(|c| c.value(state)).sum::<f64>();
+        (sum(&self.positive) - sum(&self.negative)).clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Binding {
+    // pressed while any of the controls is
+    Button(Vec<Control>),
+    Axis(AxisBinding),
+    Axis2 { x: AxisBinding, y: AxisBinding },
+}
+
+impl Binding {
+    fn value(&self, state: &InputState) -> [f64; 2] {
+        match self {
+            Binding::Button(controls) => {
+                let pressed = controls.iter().any(|c| c.value(state) > PRESS_THRESHOLD);
+                [pressed as u32 as f64, 0.0]
+            }
+            Binding::Axis(axis) => [axis.value(state), 0.0],
+            Binding::Axis2 { x, y } => [x.value(state), y.value(state)],
+        }
+    }
+    fn is_down(&self, state: &InputState) -> bool {
+        let [x, y] = self.value(state);
+        x.hypot(y) > PRESS_THRESHOLD
+    }
+    // whether any control of a button went through the given transition
+    fn any_control(&self, transition: impl Fn(&Control) -> bool) -> bool {
+        match self {
+            Binding::Button(controls) => controls.iter().any(transition),
+            _ => false,
+        }
+    }
+}
+
+// the bindings of all actions by name
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ActionMap {
+    bindings: BTreeMap<String, Binding>,
+}
+
+fn axis(positive: &[&str], negative: &[&str]) -> AxisBinding {
+    let controls = |names: &[&str]| names.iter().map(|n| n.parse().unwrap()).collect();
+    AxisBinding {
+        positive: controls(positive),
+        negative: controls(negative),
+    }
+}
+
+// the controls the scenes are written for
+impl Default for ActionMap {
+    fn default() -> Self {
+        let mut map = ActionMap {
+            bindings: BTreeMap::new(),
+        };
+        // x goes right and y forward, or up the screen
+        map.bind(
+            "move",
+            Binding::Axis2 {
+                x: axis(
+                    &["KeyD", "ArrowRight", "gamepad:DpadRight", "gamepad:LeftX"],
+                    &["KeyA", "ArrowLeft", "gamepad:DpadLeft"],
+                ),
+                y: axis(
+                    &["KeyW", "ArrowUp", "gamepad:DpadUp"],
+                    &["KeyS", "ArrowDown", "gamepad:DpadDown", "gamepad:LeftY"],
+                ),
+            },
+        );
+        // the mouse turns the camera directly, this is for controls that turn it over time
+        map.bind(
+            "look",
+            Binding::Axis2 {
+                x: axis(&["gamepad:RightX"], &[]),
+                y: axis(&["gamepad:RightY"], &[]),
+            },
+        );
+        map.bind(
+            "rise",
+            Binding::Axis(axis(
+                &["KeyE", "gamepad:RightTrigger"],
+                &["KeyQ", "gamepad:LeftTrigger"],
+            )),
+        );
+        map
+    }
+}
+
+impl ActionMap {
+    // the default bindings, with the ones in the file replacing those of the same name
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ActionMapError> {
+        let loaded: ActionMap = serde_json::from_slice(&std::fs::read(path)?)?;
+        let mut map = ActionMap::default();
+        map.bindings.extend(loaded.bindings);
+        Ok(map)
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ActionMapError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.bindings.insert(action.to_string(), binding);
+    }
+    pub fn unbind(&mut self, action: &str) -> Option<Binding> {
+        self.bindings.remove(action)
+    }
+    pub fn binding(&self, action: &str) -> Option<&Binding> {
+        self.bindings.get(action)
+    }
+    // previous is the state at the end of the last tick, for telling presses from holds
+    pub fn actions<'a>(&'a self, state: &'a InputState, previous: &'a InputState) -> Actions<'a> {
+        Actions {
+            map: self,
+            state,
+            previous,
+        }
+    }
+}
+
+// the actions as of the current input, what scenes get each update. It derefs to the raw
+// InputState for what isn't an action, like the mouse position.
+pub struct Actions<'a> {
+    map: &'a ActionMap,
+    state: &'a InputState,
+    previous: &'a InputState,
+}
+
+impl Actions<'_> {
+    // actions without a binding are never active
+    fn value(&self, action: &str) -> [f64; 2] {
+        self.map
+            .binding(action)
+            .map_or([0.0; 2], |b| b.value(self.state))
+    }
+    // for axes, whether it is pushed far enough to either side
+    pub fn is_down(&self, action: &str) -> bool {
+        self.map
+            .binding(action)
+            .is_some_and(|b| b.is_down(self.state))
+    }
+    // whether the action went down during this tick, only true in one update per press
+    pub fn was_pressed(&self, action: &str) -> bool {
+        self.map.binding(action).is_some_and(|b| {
+            b.is_down(self.state) && !b.is_down(self.previous)
+                || b.any_control(|c| c.was_pressed(self.state))
+        })
+    }
+    pub fn was_released(&self, action: &str) -> bool {
+        self.map.binding(action).is_some_and(|b| {
+            !b.is_down(self.state) && b.is_down(self.previous)
+                || b.any_control(|c| c.was_released(self.state))
+        })
+    }
+    // from -1 to 1, the x part of 2D axes
+    pub fn axis(&self, action: &str) -> f64 {
+        self.value(action)[0]
+    }
+    pub fn axis2(&self, action: &str) -> [f64; 2] {
+        self.value(action)
+    }
+}
+
+impl Deref for Actions<'_> {
+    type Target = InputState;
+    fn deref(&self) -> &InputState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_after(events: &[InputEvent]) -> InputState {
+        let mut state = InputState::default();
+        for event in events {
+            state.update(event.clone());
+        }
+        state
+    }
+
+    #[test]
+    fn control_names_round_trip() {
+        for (name, control) in [
+            ("KeyA", Control::Key(Key::KeyA)),
+            ("mouse:Left", Control::MouseButton(MouseButton::Left)),
+            ("gamepad:LeftX", Control::GamepadAxis(GamepadAxis::LeftX)),
+            (
+                "gamepad:South",
+                Control::GamepadButton(GamepadButton::South),
+            ),
+        ] {
+            assert_eq!(name.parse::<Control>(), Ok(control));
+            assert_eq!(control.to_string(), name);
+        }
+        for name in [
+            "KeyNone",
+            "mouse:South",
+            "gamepad:Left",
+            "joystick:LeftX",
+            "mouse:",
+        ] {
+            assert_eq!(
+                name.parse::<Control>(),
+                Err(format!("unknown control {name}"))
+            );
+        }
+    }
+
+    #[test]
+    fn load_replaces_default_bindings() {
+        let path = std::env::temp_dir().join(format!("actions_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "move": { "axis": { "positive": ["KeyL"] } },
+                "jump": { "button": ["Space", "gamepad:South"] }
+            }"#,
+        )
+        .unwrap();
+        let map = ActionMap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let Some(Binding::Axis(axis)) = map.binding("move") else {
+            panic!(
+                "move isn't the axis from the file: {:?}",
+                map.binding("move")
+            );
+        };
+        assert_eq!(axis.positive, [Control::Key(Key::KeyL)]);
+        assert!(axis.negative.is_empty());
+        assert!(matches!(map.binding("jump"), Some(Binding::Button(c)) if c.len() == 2));
+        // the bindings the file doesn't mention stay
+        assert!(matches!(map.binding("look"), Some(Binding::Axis2 { .. })));
+        assert!(matches!(map.binding("rise"), Some(Binding::Axis(_))));
+    }
+
+    #[test]
+    fn axis_value_is_clamped() {
+        let map = ActionMap::default();
+        let previous = InputState::default();
+        let state = state_after(&[
+            InputEvent::KeyDown(Key::KeyD),
+            InputEvent::GamepadAxis(GamepadAxis::LeftX, 0.8),
+            InputEvent::KeyDown(Key::KeyS),
+            InputEvent::GamepadAxis(GamepadAxis::LeftY, 0.5),
+        ]);
+        assert_eq!(map.actions(&state, &previous).axis2("move"), [1.0, -1.0]);
+        // opposite sides cancel out before the clamp
+        let state = state_after(&[
+            InputEvent::KeyDown(Key::KeyD),
+            InputEvent::GamepadAxis(GamepadAxis::LeftX, -1.0),
+        ]);
+        assert_eq!(map.actions(&state, &previous).axis("move"), 0.0);
+    }
+
+    #[test]
+    fn axis_was_pressed() {
+        let map = ActionMap::default();
+        let rest = state_after(&[InputEvent::GamepadAxis(GamepadAxis::LeftX, 0.2)]);
+        let pushed = state_after(&[InputEvent::GamepadAxis(GamepadAxis::LeftX, 0.9)]);
+        assert!(map.actions(&pushed, &rest).was_pressed("move"));
+        assert!(!map.actions(&pushed, &pushed).was_pressed("move"));
+        assert!(!map.actions(&rest, &rest).was_pressed("move"));
+        assert!(map.actions(&rest, &pushed).was_released("move"));
+    }
+}
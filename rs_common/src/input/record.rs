@@ -0,0 +1,142 @@
+// Input recordings: InputRecorder wraps an InputSource and writes every event with the update
+// it was seen by, and InputReplay feeds a recording back so that each event reaches the same
+// update again. As scenes advance in fixed steps and take their randomness from the session
+// seed, a replay plays out exactly like the recorded session, on any backend.
+//
+// The file is JSON lines, the Session first and then one RecordedEvent per line.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::*;
+use crate::runner::Session;
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+    #[error("json error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("empty recording")]
+    Empty,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    // the update that saw the event
+    pub tick: u64,
+    // seconds since the recording started, only for reading the file
+    pub time: f64,
+    pub event: InputEvent,
+}
+
+pub struct InputRecorder<S> {
+    source: S,
+    // None once writing failed
+    file: Option<LineWriter<File>>,
+    start: Instant,
+    tick: u64,
+}
+
+impl<S: InputSource> InputRecorder<S> {
+    pub fn create(
+        path: impl AsRef<Path>,
+        session: &Session,
+        source: S,
+    ) -> Result<Self, RecordingError> {
+        // every line is flushed, so that a recording survives a crash of the session
+        let mut file = LineWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, session)?;
+        file.write_all(b"\n")?;
+        Ok(InputRecorder {
+            source,
+            file: Some(file),
+            start: Instant::now(),
+            tick: 0,
+        })
+    }
+    fn write(&mut self, event: &InputEvent) -> Result<(), RecordingError> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let recorded = RecordedEvent {
+            tick: self.tick,
+            time: self.start.elapsed().as_secs_f64(),
+            event: event.clone(),
+        };
+        serde_json::to_writer(&mut *file, &recorded)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl<S: InputSource> InputSource for InputRecorder<S> {
+    fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        self.source.set_tick(tick);
+    }
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        let event = self.source.poll_event()?;
+        if let Err(err) = self.write(&event) {
+            // the session goes on, only the recording is lost
+            eprintln!("input recording error: {err:?}");
+            self.file = None;
+        }
+        Some(event)
+    }
+}
+
+pub struct InputReplay {
+    events: VecDeque<RecordedEvent>,
+    tick: u64,
+    // one past the last update that sees an event
+    ticks: u64,
+}
+
+impl InputReplay {
+    // the session the recording was made in, and a source replaying its input
+    pub fn load(path: impl AsRef<Path>) -> Result<(Session, InputReplay), RecordingError> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let session = serde_json::from_str(&lines.next().ok_or(RecordingError::Empty)??)?;
+        let mut events = VecDeque::new();
+        for line in lines {
+            events.push_back(serde_json::from_str::<RecordedEvent>(&line?)?);
+        }
+        let ticks = events.back().map_or(0, |e| e.tick + 1);
+        Ok((
+            session,
+            InputReplay {
+                events,
+                tick: 0,
+                ticks,
+            },
+        ))
+    }
+    // the number of updates needed to replay all events
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl InputSource for InputReplay {
+    fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        if self.events.front()?.tick > self.tick {
+            return None;
+        }
+        self.events.pop_front().map(|e| e.event)
+    }
+}
@@ -9,4 +9,5 @@ pub mod input;
 pub mod gltf;
 pub mod animation;
 pub mod collision;
-pub mod entity;
\ No newline at end of file
+pub mod entity;
+pub mod runner;
\ No newline at end of file
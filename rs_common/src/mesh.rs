@@ -1,51 +1,57 @@
 use std::{
-    cell::RefCell, collections::HashMap, io::{BufRead, BufReader, Cursor, Seek}, ops::Range, path::PathBuf, rc::{Rc, Weak}
+    cell::{OnceCell, RefCell}, collections::HashMap, io::{BufRead, BufReader, Cursor, Seek}, ops::Range, path::PathBuf, rc::{Rc, Weak}
 };
 
 use crate::{
     assets::{resolve_path, AssetLoader},
     geometry::{Triangle, Vec2, Vec3},
-    render::{self, Backend, Context, TextureId, Triangle4},
+    render::{self, AlphaState, Attributes, Backend, BufferId, BufferReleaser, Context, SamplerState, TextureId, TextureReleaser, Triangle4},
 };
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    pub a: u8,
 }
 
 impl Color {
-    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
-    pub const WHITE: Color = Color {
-        r: 255,
-        g: 255,
-        b: 255,
-    };
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
     pub fn as_u32(self) -> u32 {
-        (self.r as u32) | (self.g as u32) << 8 | (self.b as u32) << 16
+        (self.r as u32) | (self.g as u32) << 8 | (self.b as u32) << 16 | (self.a as u32) << 24
+    }
+    pub fn lerp(self, other: Color, l: f64) -> Color {
+        let f = |a: u8, b: u8| (a as f64 * l + b as f64 * (1.0 - l)).round() as u8;
+        Color {
+            r: f(self.r, other.r),
+            g: f(self.g, other.g),
+            b: f(self.b, other.b),
+            a: f(self.a, other.a),
+        }
     }
 }
 
+// scales the color channels, alpha is left untouched.
 impl std::ops::Mul<f64> for Color {
     type Output = Color;
     fn mul(self, rhs: f64) -> Self::Output {
         let r = ((self.r as f64) * rhs) as u8;
         let g = ((self.g as f64) * rhs) as u8;
         let b = ((self.b as f64) * rhs) as u8;
-        Color { r, g, b }
+        Color { r, g, b, a: self.a }
     }
 }
 
 impl From<[f64; 3]> for Color {
     fn from(value: [f64; 3]) -> Self {
         let f = |v: f64| (v * 255.0) as u8;
-        Color {
-            r: f(value[0]),
-            g: f(value[1]),
-            b: f(value[2]),
-        }
+        Color::rgb(f(value[0]), f(value[1]), f(value[2]))
     }
 }
 
@@ -56,6 +62,7 @@ impl From<[f64; 4]> for Color {
             r: f(value[0]),
             g: f(value[1]),
             b: f(value[2]),
+            a: f(value[3]),
         }
     }
 }
@@ -65,7 +72,7 @@ pub enum TextureState {
     File(PathBuf),
     Memory(Vec<u8>),
     RenderTexture(render::Texture<'static>),
-    Backend(TextureId),
+    Backend(TextureId, TextureReleaser),
     Error,
 }
 
@@ -74,9 +81,44 @@ pub struct Texture {
     pub state: RefCell<TextureState>,
 }
 
+impl Drop for Texture {
+    fn drop(&mut self) {
+        if let TextureState::Backend(id, releaser) = self.state.get_mut() {
+            releaser.release(*id);
+        }
+    }
+}
+
+// the retained buffer of a loaded mesh, released when the mesh is dropped. Clones start out
+// unloaded, as their vertices are usually changed.
+#[derive(Debug, Default)]
+pub struct MeshBuffer(OnceCell<(BufferId, BufferReleaser)>);
+
+impl MeshBuffer {
+    pub fn id(&self) -> Option<BufferId> {
+        self.0.get().map(|(id, _)| *id)
+    }
+}
+
+impl Clone for MeshBuffer {
+    fn clone(&self) -> Self {
+        MeshBuffer::default()
+    }
+}
+
+impl Drop for MeshBuffer {
+    fn drop(&mut self) {
+        if let Some((id, releaser)) = self.0.get() {
+            releaser.release(*id);
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Material {
     pub texture: Option<Rc<Texture>>,
+    pub sampler: SamplerState,
+    pub alpha: AlphaState,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -86,6 +128,7 @@ pub struct Mesh {
     pub color: Vec<Color>,
     pub triangle_indices: Vec<[usize; 3]>,
     pub material_ranges: Vec<(Rc<Material>, Range<usize>)>,
+    pub buffer: MeshBuffer,
 }
 
 impl Mesh {
@@ -99,8 +142,10 @@ impl Mesh {
         let [i0, i1, i2] = self.triangle_indices[i];
         Triangle4 {
             vertices: [self.vertices[i0].into(), self.vertices[i1].into(), self.vertices[i2].into()],
-            uv: [self.uv[i0], self.uv[i1], self.uv[i2]],
-            color: [self.color[i0], self.color[i1], self.color[i2]],
+            attributes: [i0, i1, i2].map(|i| Attributes {
+                uv: self.uv[i],
+                color: self.color[i],
+            }),
         }
     }
 }
@@ -145,6 +190,8 @@ impl Texture {
                 let texture = Rc::new(Texture {
                     state: RefCell::new(TextureState::File(path.clone())),
                 });
+                // drop the entries of textures that have since been freed
+                texture_by_name.retain(|_, t| t.strong_count() > 0);
                 texture_by_name.insert(path, Rc::downgrade(&texture));
                 texture
             }
@@ -169,9 +216,9 @@ impl Texture {
                     let image_reader = image::ImageReader::new(Cursor::new(data));
                     TextureState::RenderTexture(image_reader_to_render_texture(image_reader))
                 }
-                TextureState::RenderTexture(_) | TextureState::Backend(_) | TextureState::Error => {
-                    state
-                }
+                TextureState::RenderTexture(_)
+                | TextureState::Backend(..)
+                | TextureState::Error => state,
             },
         )
     }
@@ -182,16 +229,17 @@ impl Texture {
             TextureState::Error,
             |state| match state {
                 TextureState::File(_) | TextureState::Memory(_) => unreachable!(),
-                TextureState::RenderTexture(texture) => {
-                    TextureState::Backend(context.load_texture(texture).unwrap())
-                }
-                TextureState::Backend(_) | TextureState::Error => state,
+                TextureState::RenderTexture(texture) => TextureState::Backend(
+                    context.load_texture(texture).unwrap(),
+                    context.texture_releaser(),
+                ),
+                TextureState::Backend(..) | TextureState::Error => state,
             },
         )
     }
     pub fn texture_id(&self) -> TextureId {
         match *self.state.borrow() {
-            TextureState::Backend(texture_id) => texture_id,
+            TextureState::Backend(texture_id, _) => texture_id,
             _ => unreachable!(),
         }
     }
@@ -210,5 +258,18 @@ impl Mesh {
                 tex.load_backend(context, loader);
             }
         }
+        if self.buffer.id().is_none() {
+            let attributes = self
+                .uv
+                .iter()
+                .zip(&self.color)
+                .map(|(&uv, &color)| Attributes { uv, color })
+                .collect::<Vec<_>>();
+            let buffer = context.load_buffer(&self.vertices, &attributes, &self.triangle_indices);
+            let _ = self.buffer.0.set((buffer, context.buffer_releaser()));
+        }
+    }
+    pub fn buffer_id(&self) -> BufferId {
+        self.buffer.id().expect("mesh used before being loaded")
     }
 }
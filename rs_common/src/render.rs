@@ -1,17 +1,65 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::rc::Rc;
 
 use crate::{geometry::{Matrix, Vec2, Vec3, Vec4}, mesh::Color};
 
-pub const WIDTH: usize = 640;
-pub const HEIGHT: usize = 480;
+pub mod capture;
+pub mod fixed;
+
 pub const TILE_SIZE: usize = 4;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Viewport {
+    pub fn new(width: usize, height: usize) -> Self {
+        Viewport { width, height }
+    }
+    pub fn projection(&self, fov_y: f64, near: f64, far: f64) -> Matrix {
+        Matrix::projection(fov_y, self.width as f64, self.height as f64, near, far)
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::new(640, 480)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes {
+    pub uv: Vec2,
+    pub color: Color,
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Attributes {
+            uv: Vec2::default(),
+            color: Color::WHITE,
+        }
+    }
+}
+
+impl Attributes {
+    pub fn lerp(self, other: Attributes, l: f64) -> Attributes {
+        Attributes {
+            uv: self.uv.lerp(other.uv, l),
+            color: self.color.lerp(other.color, l),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Triangle4 {
     pub vertices: [Vec4; 3],
-    pub uv: [Vec2; 3],
-    pub color: [Color; 3],
+    pub attributes: [Attributes; 3],
 }
 
 #[derive(Debug, Clone)]
@@ -33,9 +81,14 @@ fn clip_line(a: Vec4, b: Vec4, c: Vec4) -> f64 {
 impl Triangle4 {
     pub fn new(data: [[f64; 5]; 3]) -> Self {
         let vertices = data.map(|d| [d[0], d[1], d[2], 1.0].into());
-        let uv = data.map(|d| [d[3], d[4]].into());
-        let color = [Color::WHITE; 3];
-        Triangle4 { vertices, uv, color }
+        let attributes = data.map(|d| Attributes {
+            uv: [d[3], d[4]].into(),
+            color: Color::WHITE,
+        });
+        Triangle4 {
+            vertices,
+            attributes,
+        }
     }
     pub fn transform(&self, matrix: Matrix) -> Self {
         Triangle4 {
@@ -43,14 +96,20 @@ impl Triangle4 {
             ..self.clone()
         }
     }
-    fn clip_corner(&self, i: usize, j: usize, k: usize, plane: Vec4) -> (Vec4, Vec2, Vec4, Vec2) {
+    fn clip_corner(
+        &self,
+        i: usize,
+        j: usize,
+        k: usize,
+        plane: Vec4,
+    ) -> (Vec4, Attributes, Vec4, Attributes) {
         let a = clip_line(self.vertices[i], self.vertices[j], plane);
         let b = clip_line(self.vertices[i], self.vertices[k], plane);
         let va = self.vertices[i].lerp(self.vertices[j], a);
-        let uva = self.uv[i].lerp(self.uv[j], a);
+        let attra = self.attributes[i].lerp(self.attributes[j], a);
         let vb = self.vertices[i].lerp(self.vertices[k], b);
-        let uvb = self.uv[i].lerp(self.uv[k], b);
-        (va, uva, vb, uvb)
+        let attrb = self.attributes[i].lerp(self.attributes[k], b);
+        (va, attra, vb, attrb)
     }
     fn clip(&self, plane: Vec4) -> Vec<Self> {
         let mut clipcode: u8 = 0;
@@ -65,19 +124,15 @@ impl Triangle4 {
                 let i = clipcode.trailing_zeros() as usize;
                 let j = (i + 1) % 3;
                 let k = (i + 2) % 3;
-                let (va, uva, vb, uvb) = self.clip_corner(i, j, k, plane);
+                let (va, attra, vb, attrb) = self.clip_corner(i, j, k, plane);
                 vec![
                     Triangle4 {
                         vertices: [va, self.vertices[j], self.vertices[k]],
-                        uv: [uva, self.uv[j], self.uv[k]],
-                        // TODO: fix this
-                        color: self.color,
+                        attributes: [attra, self.attributes[j], self.attributes[k]],
                     },
                     Triangle4 {
                         vertices: [va, vb, self.vertices[k]],
-                        uv: [uva, uvb, self.uv[k]],
-                        // TODO: fix this
-                        color: self.color,
+                        attributes: [attra, attrb, self.attributes[k]],
                     },
                 ]
             }
@@ -85,12 +140,10 @@ impl Triangle4 {
                 let i = (7 ^ clipcode).trailing_zeros() as usize;
                 let j = (i + 1) % 3;
                 let k = (i + 2) % 3;
-                let (va, uva, vb, uvb) = self.clip_corner(i, j, k, plane);
+                let (va, attra, vb, attrb) = self.clip_corner(i, j, k, plane);
                 vec![Triangle4 {
                     vertices: [self.vertices[i], va, vb],
-                    uv: [self.uv[i], uva, uvb],
-                    // TODO: fix this
-                    color: self.color,
+                    attributes: [self.attributes[i], attra, attrb],
                 }]
             }
             0b000 => vec![self.clone()],
@@ -132,8 +185,12 @@ impl Triangle4 {
             ])
         }
     }
-    fn extent(&self, xy: usize) -> Option<[f64; 2]> {
-        let size = if xy != 0 { HEIGHT } else { WIDTH } as f64;
+    fn extent(&self, xy: usize, viewport: Viewport) -> Option<[f64; 2]> {
+        let size = if xy != 0 {
+            viewport.height
+        } else {
+            viewport.width
+        } as f64;
         let mut min = size;
         let mut max = 0.0;
         let mut lr = [0; 3];
@@ -174,31 +231,50 @@ impl Triangle4 {
             Some([min, max])
         }
     }
-    fn bbox(&self) -> Option<BBox> {
-        let [x0, x1] = self.extent(0)?;
-        let [y0, y1] = self.extent(1)?;
+    fn bbox(&self, viewport: Viewport) -> Option<BBox> {
+        let [x0, x1] = self.extent(0, viewport)?;
+        let [y0, y1] = self.extent(1, viewport)?;
+        let max_tx = ((viewport.width - 1) / TILE_SIZE) as f64;
+        let max_ty = ((viewport.height - 1) / TILE_SIZE) as f64;
         Some(BBox {
-            min_x: (x0 / TILE_SIZE as f64).clamp(0.0, ((WIDTH - 1) / TILE_SIZE) as f64) as usize,
-            min_y: (y0 / TILE_SIZE as f64).clamp(0.0, ((HEIGHT - 1) / TILE_SIZE) as f64) as usize,
-            max_x: (x1 / TILE_SIZE as f64).clamp(0.0, ((WIDTH - 1) / TILE_SIZE) as f64) as usize,
-            max_y: (y1 / TILE_SIZE as f64).clamp(0.0, ((HEIGHT - 1) / TILE_SIZE) as f64) as usize,
+            min_x: (x0 / TILE_SIZE as f64).clamp(0.0, max_tx) as usize,
+            min_y: (y0 / TILE_SIZE as f64).clamp(0.0, max_ty) as usize,
+            max_x: (x1 / TILE_SIZE as f64).clamp(0.0, max_tx) as usize,
+            max_y: (y1 / TILE_SIZE as f64).clamp(0.0, max_ty) as usize,
         })
     }
     pub fn lighting(&self, ambient: f64, diffuse: f64, direction: Vec3) -> Self {
-        let normal = Vec3::cross(
-            self.vertices[0].xyz() - self.vertices[1].xyz(),
-            self.vertices[0].xyz() - self.vertices[2].xyz(),
-        )
-        .normalize();
-        let l = (direction * normal).clamp(0.0, 1.0);
-        let ll = (ambient + l * diffuse).clamp(0.0, 1.0);
+        let ll = Lighting {
+            ambient,
+            diffuse,
+            direction,
+        }
+        .factor(self.vertices.map(Vec4::xyz));
         Self {
-            color: self.color.map(|c| c * ll),
+            attributes: self.attributes.map(|a| Attributes {
+                color: a.color * ll,
+                ..a
+            }),
             ..self.clone()
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Lighting {
+    pub ambient: f64,
+    pub diffuse: f64,
+    pub direction: Vec3,
+}
+
+impl Lighting {
+    pub fn factor(&self, vertices: [Vec3; 3]) -> f64 {
+        let normal = Vec3::cross(vertices[0] - vertices[1], vertices[0] - vertices[2]).normalize();
+        let l = (self.direction * normal).clamp(0.0, 1.0);
+        (self.ambient + l * self.diffuse).clamp(0.0, 1.0)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BackendTriangle {
     pub edge_mat: [[f64; 3]; 3],
@@ -208,8 +284,8 @@ pub struct BackendTriangle {
 }
 
 impl BackendTriangle {
-    pub fn new(p: &Triangle4) -> Option<Self> {
-        let bbox = p.bbox()?;
+    pub fn new(p: &Triangle4, viewport: Viewport) -> Option<Self> {
+        let bbox = p.bbox(viewport)?;
         let mut edge_mat = p.edge_mat()?;
         for i in 0..3 {
             edge_mat[2][i] += edge_mat[0][i] * (bbox.min_x * TILE_SIZE) as f64;
@@ -217,8 +293,8 @@ impl BackendTriangle {
         }
         Some(BackendTriangle {
             edge_mat,
-            uv: p.uv.map(|x| *x),
-            rgb: p.color.map(|c| c.as_u32()),
+            uv: p.attributes.map(|a| *a.uv),
+            rgb: p.attributes.map(|a| a.color.as_u32()),
             bbox,
         })
     }
@@ -237,29 +313,227 @@ pub struct Texture<'a> {
     pub ty: TextureType,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Wrap,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerState {
+    pub wrap: WrapMode,
+    pub border: Color,
+}
+
+impl Default for SamplerState {
+    fn default() -> Self {
+        SamplerState {
+            wrap: WrapMode::Wrap,
+            border: Color::BLACK,
+        }
+    }
+}
+
+impl SamplerState {
+    // maps a texel coordinate onto 0..size, or None if the border color should be used.
+    pub fn texel(&self, t: i64, size: usize) -> Option<usize> {
+        let size = size as i64;
+        match self.wrap {
+            WrapMode::Wrap => Some(t.rem_euclid(size) as usize),
+            WrapMode::ClampToEdge => Some(t.clamp(0, size - 1) as usize),
+            WrapMode::ClampToBorder => (0..size).contains(&t).then_some(t as usize),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthMode {
+    Always,
+    Never,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl DepthMode {
+    pub fn test<T: PartialOrd>(self, fragment: T, buffer: T) -> bool {
+        match self {
+            DepthMode::Always => true,
+            DepthMode::Never => false,
+            DepthMode::Lt => fragment < buffer,
+            DepthMode::Le => fragment <= buffer,
+            DepthMode::Gt => fragment > buffer,
+            DepthMode::Ge => fragment >= buffer,
+            DepthMode::Eq => fragment == buffer,
+            DepthMode::Ne => fragment != buffer,
+        }
+    }
+}
+
+// depth values grow towards the camera, so Gt keeps the closest fragment. Without write,
+// passing fragments are drawn but leave the depth buffer as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthState {
+    pub mode: DepthMode,
+    pub write: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        DepthState {
+            mode: DepthMode::Gt,
+            write: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    // fragments with an alpha below the cutoff are discarded
+    Mask,
+    // fragments are blended over the target, draws should be sorted back to front
+    Blend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlphaState {
+    pub mode: AlphaMode,
+    pub cutoff: f64,
+}
+
+impl Default for AlphaState {
+    fn default() -> Self {
+        AlphaState {
+            mode: AlphaMode::Opaque,
+            cutoff: 0.5,
+        }
+    }
+}
+
+impl AlphaState {
+    pub fn is_transparent(&self) -> bool {
+        self.mode == AlphaMode::Blend
+    }
+}
+
 pub trait Backend {
     type Texture;
+    type RenderTarget;
     type Error: Debug;
+    fn viewport(&self) -> Viewport;
     fn load_texture(&mut self, texture: Texture) -> Result<Self::Texture, Self::Error>;
+    fn unload_texture(&mut self, texture: Self::Texture);
     fn use_texture(&mut self, texture: Option<&Self::Texture>);
+    fn create_render_target(
+        &mut self,
+        viewport: Viewport,
+    ) -> Result<(Self::RenderTarget, Self::Texture), Self::Error>;
+    // None selects the screen. The new target's color and depth buffers are cleared.
+    fn set_render_target(&mut self, target: Option<&Self::RenderTarget>, clear: Color);
+    fn set_sampler(&mut self, sampler: SamplerState);
+    fn set_depth(&mut self, depth: DepthState);
+    fn set_alpha(&mut self, alpha: AlphaState);
     fn draw(&mut self, triangles: &[BackendTriangle]);
+    // selects the screen as render target and clears it.
+    fn begin_frame(&mut self, clear: Color);
+    // all draws of the frame have been issued, an active render target is resolved.
+    fn end_frame(&mut self);
+    // makes the finished frame visible, e.g. by waiting for vsync and flipping buffers.
+    fn present(&mut self);
+}
+
+struct VertexBuffer {
+    positions: Vec<Vec3>,
+    attributes: Vec<Attributes>,
+    indices: Vec<[usize; 3]>,
+}
+
+// vertices of the most recently drawn buffer, so that consecutive draws
+// of the same buffer (e.g. one per material) only transform once.
+#[derive(Default)]
+struct TransformCache {
+    key: Option<(BufferId, Matrix, Matrix)>,
+    world: Vec<Vec3>,
+    clip: Vec<Vec4>,
+}
+
+// lets owners of a TextureId (e.g. mesh::Texture on drop) free it without
+// borrowing the Context, the texture is unloaded on the next draw or load.
+#[derive(Clone, Debug, Default)]
+pub struct TextureReleaser(Rc<RefCell<Vec<TextureId>>>);
+
+impl TextureReleaser {
+    pub fn release(&self, id: TextureId) {
+        self.0.borrow_mut().push(id);
+    }
+}
+
+// the same for the retained buffers of dropped meshes.
+#[derive(Clone, Debug, Default)]
+pub struct BufferReleaser(Rc<RefCell<Vec<BufferId>>>);
+
+impl BufferReleaser {
+    pub fn release(&self, id: BufferId) {
+        self.0.borrow_mut().push(id);
+    }
+}
+
+struct RenderTarget<B: Backend> {
+    target: B::RenderTarget,
+    texture: TextureId,
+    viewport: Viewport,
 }
 
 pub struct Context<B: Backend> {
     backend: B,
-    textures: Vec<B::Texture>,
+    viewport: Viewport,
+    // None once freed, ids are never reused
+    textures: Vec<Option<B::Texture>>,
+    releaser: TextureReleaser,
+    render_targets: Vec<RenderTarget<B>>,
+    current_target: Option<RenderTargetId>,
+    // None once freed, ids are never reused
+    buffers: Vec<Option<VertexBuffer>>,
+    buffer_releaser: BufferReleaser,
+    transform_cache: TransformCache,
+    triangles: Vec<BackendTriangle>,
     current_texture: Option<TextureId>,
+    current_sampler: Option<SamplerState>,
+    current_depth: Option<DepthState>,
+    current_alpha: Option<AlphaState>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct TextureId(u32);
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BufferId(u32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RenderTargetId(u32);
+
 impl<B: Backend> Context<B> {
     pub fn new(backend: B) -> Self {
         Context {
+            viewport: backend.viewport(),
             backend,
             textures: Vec::new(),
+            releaser: TextureReleaser::default(),
+            render_targets: Vec::new(),
+            current_target: None,
+            buffers: Vec::new(),
+            buffer_releaser: BufferReleaser::default(),
+            transform_cache: TransformCache::default(),
+            triangles: Vec::new(),
             current_texture: None,
+            current_sampler: None,
+            current_depth: None,
+            current_alpha: None,
         }
     }
     pub fn backend(&self) -> &B {
@@ -268,16 +542,145 @@ impl<B: Backend> Context<B> {
     pub fn backend_mut(&mut self) -> &mut B {
         &mut self.backend
     }
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
     pub fn load_texture(&mut self, texture: Texture) -> Result<TextureId, B::Error> {
+        self.free_released();
         let id = TextureId(self.textures.len().try_into().unwrap());
         let tex = self.backend.load_texture(texture)?;
-        self.textures.push(tex);
+        self.textures.push(Some(tex));
+        Ok(id)
+    }
+    pub fn free_texture(&mut self, id: TextureId) {
+        assert!(
+            self.render_targets.iter().all(|rt| rt.texture != id),
+            "render target textures can't be freed"
+        );
+        let tex = self.textures[id.0 as usize]
+            .take()
+            .expect("texture freed twice");
+        if self.current_texture == Some(id) {
+            self.backend.use_texture(None);
+            self.current_texture = None;
+        }
+        self.backend.unload_texture(tex);
+    }
+    pub fn texture_releaser(&self) -> TextureReleaser {
+        self.releaser.clone()
+    }
+    fn free_released(&mut self) {
+        let released = std::mem::take(&mut *self.releaser.0.borrow_mut());
+        for id in released {
+            self.free_texture(id);
+        }
+        let released = std::mem::take(&mut *self.buffer_releaser.0.borrow_mut());
+        for id in released {
+            self.free_buffer(id);
+        }
+    }
+    pub fn create_render_target(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> Result<RenderTargetId, B::Error> {
+        assert!(width.is_power_of_two() && height.is_power_of_two());
+        let viewport = Viewport::new(width, height);
+        let (target, tex) = self.backend.create_render_target(viewport)?;
+        let texture = TextureId(self.textures.len().try_into().unwrap());
+        self.textures.push(Some(tex));
+        let id = RenderTargetId(self.render_targets.len().try_into().unwrap());
+        self.render_targets.push(RenderTarget {
+            target,
+            texture,
+            viewport,
+        });
         Ok(id)
     }
+    pub fn render_target_texture(&self, id: RenderTargetId) -> TextureId {
+        self.render_targets[id.0 as usize].texture
+    }
+    // switching targets clears the new one, so render targets should be drawn
+    // before the scene that samples them.
+    pub fn set_render_target(&mut self, target: Option<RenderTargetId>, clear: Color) {
+        let rt = target.map(|id| &self.render_targets[id.0 as usize]);
+        self.backend.set_render_target(rt.map(|rt| &rt.target), clear);
+        self.viewport = rt.map_or(self.backend.viewport(), |rt| rt.viewport);
+        self.current_target = target;
+    }
+    pub fn begin_frame(&mut self, clear: Color) {
+        self.free_released();
+        self.backend.begin_frame(clear);
+        self.viewport = self.backend.viewport();
+        self.current_target = None;
+    }
+    pub fn end_frame(&mut self) {
+        self.backend.end_frame();
+        self.viewport = self.backend.viewport();
+        self.current_target = None;
+    }
+    pub fn present(&mut self) {
+        self.backend.present();
+    }
+    pub fn current_render_target(&self) -> Option<RenderTargetId> {
+        self.current_target
+    }
+    pub fn load_buffer(
+        &mut self,
+        positions: &[Vec3],
+        attributes: &[Attributes],
+        indices: &[[usize; 3]],
+    ) -> BufferId {
+        assert!(positions.len() == attributes.len());
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()));
+        self.free_released();
+        let id = BufferId(self.buffers.len().try_into().unwrap());
+        self.buffers.push(Some(VertexBuffer {
+            positions: positions.to_vec(),
+            attributes: attributes.to_vec(),
+            indices: indices.to_vec(),
+        }));
+        id
+    }
+    pub fn free_buffer(&mut self, id: BufferId) {
+        self.buffers[id.0 as usize]
+            .take()
+            .expect("buffer freed twice");
+        let cache = &mut self.transform_cache;
+        if cache.key.is_some_and(|(key, _, _)| key == id) {
+            *cache = TransformCache::default();
+        }
+    }
+    pub fn buffer_releaser(&self) -> BufferReleaser {
+        self.buffer_releaser.clone()
+    }
+    fn transform_buffer(&mut self, id: BufferId, world: Matrix, view: Matrix) {
+        let cache = &mut self.transform_cache;
+        if cache.key == Some((id, world, view)) {
+            return;
+        }
+        let buffer = self.buffers[id.0 as usize]
+            .as_ref()
+            .expect("drawing with a freed buffer");
+        cache.world.clear();
+        cache
+            .world
+            .extend(buffer.positions.iter().map(|&v| world * v));
+        cache.clip.clear();
+        cache
+            .clip
+            .extend(cache.world.iter().map(|&v| view * Vec4::from(v)));
+        cache.key = Some((id, world, view));
+    }
     pub fn draw(&mut self) -> DrawCall<'_, B> {
+        self.free_released();
         DrawCall {
             context: self,
             texture: None,
+            sampler: SamplerState::default(),
+            depth: DepthState::default(),
+            alpha: AlphaState::default(),
+            lighting: None,
         }
     }
 }
@@ -285,6 +688,10 @@ impl<B: Backend> Context<B> {
 pub struct DrawCall<'a, B: Backend> {
     context: &'a mut Context<B>,
     texture: Option<TextureId>,
+    sampler: SamplerState,
+    depth: DepthState,
+    alpha: AlphaState,
+    lighting: Option<Lighting>,
 }
 
 impl<B: Backend> DrawCall<'_, B> {
@@ -296,17 +703,100 @@ impl<B: Backend> DrawCall<'_, B> {
         self.texture = texture;
         self
     }
-    pub fn run(self, triangles: &[Triangle4]) {
-        let ctx = self.context;
+    pub fn sampler(mut self, sampler: SamplerState) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    pub fn depth(mut self, mode: DepthMode) -> Self {
+        self.depth.mode = mode;
+        self
+    }
+    pub fn depth_write(mut self, write: bool) -> Self {
+        self.depth.write = write;
+        self
+    }
+    pub fn alpha(mut self, alpha: AlphaState) -> Self {
+        self.alpha = alpha;
+        self
+    }
+    // per-face lighting for run_indexed, computed from world space positions.
+    // Triangle4 callers apply Triangle4::lighting themselves.
+    pub fn lighting(mut self, lighting: Lighting) -> Self {
+        self.lighting = Some(lighting);
+        self
+    }
+    fn apply_state(&mut self) {
+        let ctx = &mut *self.context;
         if ctx.current_texture != self.texture {
-            let texture = self.texture.map(|id| &ctx.textures[id.0 as usize]);
+            let texture = self.texture.map(|id| {
+                ctx.textures[id.0 as usize]
+                    .as_ref()
+                    .expect("drawing with a freed texture")
+            });
             ctx.backend.use_texture(texture);
             ctx.current_texture = self.texture;
         }
-        let tris = triangles
-            .iter()
-            .flat_map(|t| BackendTriangle::new(t))
-            .collect::<Vec<_>>();
-        ctx.backend.draw(&tris);
+        if self.texture.is_some() && ctx.current_sampler != Some(self.sampler) {
+            ctx.backend.set_sampler(self.sampler);
+            ctx.current_sampler = Some(self.sampler);
+        }
+        if ctx.current_depth != Some(self.depth) {
+            ctx.backend.set_depth(self.depth);
+            ctx.current_depth = Some(self.depth);
+        }
+        if ctx.current_alpha != Some(self.alpha) {
+            ctx.backend.set_alpha(self.alpha);
+            ctx.current_alpha = Some(self.alpha);
+        }
+    }
+    pub fn run(mut self, triangles: &[Triangle4]) {
+        self.apply_state();
+        let ctx = self.context;
+        let viewport = ctx.viewport;
+        ctx.triangles.clear();
+        ctx.triangles.extend(
+            triangles
+                .iter()
+                .flat_map(|t| BackendTriangle::new(t, viewport)),
+        );
+        ctx.backend.draw(&ctx.triangles);
+    }
+    pub fn run_indexed(
+        mut self,
+        buffer: BufferId,
+        range: Range<usize>,
+        world: Matrix,
+        view: Matrix,
+    ) {
+        self.apply_state();
+        let lighting = self.lighting;
+        let blend = self.alpha.mode == AlphaMode::Blend;
+        let ctx = self.context;
+        let viewport = ctx.viewport;
+        ctx.transform_buffer(buffer, world, view);
+        let cache = &ctx.transform_cache;
+        // transform_buffer checked that it isn't freed
+        let buffer = ctx.buffers[buffer.0 as usize].as_ref().unwrap();
+        let mut indices = Cow::Borrowed(&buffer.indices[range]);
+        if blend {
+            // back to front within the draw, the caller orders the draws themselves
+            let w = |idx: &[usize; 3]| idx.iter().map(|&i| cache.clip[i].w).sum::<f64>();
+            indices.to_mut().sort_by(|a, b| w(b).total_cmp(&w(a)));
+        }
+        ctx.triangles.clear();
+        ctx.triangles.extend(indices.iter().flat_map(|&idx| {
+            let mut triangle = Triangle4 {
+                vertices: idx.map(|i| cache.clip[i]),
+                attributes: idx.map(|i| buffer.attributes[i]),
+            };
+            if let Some(lighting) = &lighting {
+                let ll = lighting.factor(idx.map(|i| cache.world[i]));
+                for a in &mut triangle.attributes {
+                    a.color = a.color * ll;
+                }
+            }
+            BackendTriangle::new(&triangle, viewport)
+        }));
+        ctx.backend.draw(&ctx.triangles);
     }
 }
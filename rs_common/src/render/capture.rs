@@ -0,0 +1,628 @@
+// Frame captures: Recorder wraps a Backend and writes every call of one frame to a file, and
+// replay feeds such a file into any other Backend. A capture is self-contained, textures, render
+// targets and state that already exist when the frame starts are recorded as if the frame had
+// created them. Everything is stored bit-exactly, so a frame captured on the board replays in
+// the fixed-point model with identical output, as the model ignores alpha like the hardware.
+// Replaying on the board needs a bitstream that has the depth write enable.
+
+use std::{
+    borrow::Borrow,
+    fmt,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    AlphaMode, AlphaState, BBox, Backend, BackendTriangle, DepthMode, DepthState, SamplerState,
+    Texture, TextureType, Viewport, WrapMode,
+};
+use crate::mesh::Color;
+
+const MAGIC: &[u8; 4] = b"RCAP";
+const VERSION: u32 = 1;
+
+// textures and render targets are referred to by ids assigned by the recorder
+#[derive(Debug, Clone)]
+pub enum Command {
+    LoadTexture(u32, Texture<'static>),
+    UnloadTexture(u32),
+    UseTexture(Option<u32>),
+    // render target id, id of its texture
+    CreateRenderTarget(u32, u32, Viewport),
+    SetRenderTarget(Option<u32>, Color),
+    SetSampler(SamplerState),
+    SetDepth(DepthState),
+    SetAlpha(AlphaState),
+    Draw(Vec<BackendTriangle>),
+    BeginFrame(Color),
+    EndFrame,
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub viewport: Viewport,
+    pub commands: Vec<Command>,
+}
+
+impl Capture {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Capture> {
+        let data = std::fs::read(path)?;
+        let mut r = &data[..];
+        if r.len() < MAGIC.len() || &r[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a frame capture"));
+        }
+        r = &r[MAGIC.len()..];
+        if u32::decode(&mut r)? != VERSION {
+            return Err(invalid("unsupported capture version"));
+        }
+        let viewport = Viewport::decode(&mut r)?;
+        let mut commands = Vec::new();
+        while !r.is_empty() {
+            commands.push(Command::decode(&mut r)?);
+        }
+        Ok(Capture { viewport, commands })
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = MAGIC.to_vec();
+        VERSION.encode(&mut w);
+        self.viewport.encode(&mut w);
+        for command in &self.commands {
+            command.encode(&mut w);
+        }
+        std::fs::write(path, w)
+    }
+}
+
+// runs the commands of a capture on a backend. Textures loaded by the capture are unloaded
+// again at the end, the frame is left for the caller to present. A capture that uses ids it
+// never created is InvalidData, errors of the backend are passed on as ErrorKind::Other.
+pub fn replay<B: Backend>(backend: &mut B, capture: &Capture) -> io::Result<()> {
+    let mut textures: Vec<Option<B::Texture>> = Vec::new();
+    let mut render_targets: Vec<Option<B::RenderTarget>> = Vec::new();
+    // render target textures belong to their render target and are never unloaded
+    let mut owned = Vec::new();
+    // the recorder hands out ids in order, so a new id is at most one past the known ones
+    fn new_slot<T>(v: &mut Vec<Option<T>>, id: u32) -> io::Result<&mut Option<T>> {
+        let id = id as usize;
+        if id > v.len() {
+            return Err(invalid("id out of order"));
+        }
+        if id == v.len() {
+            v.push(None);
+        }
+        Ok(&mut v[id])
+    }
+    fn get<'a, T>(v: &'a [Option<T>], id: u32, msg: &str) -> io::Result<&'a T> {
+        v.get(id as usize)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| invalid(msg))
+    }
+    fn backend_error(err: impl fmt::Debug) -> io::Error {
+        io::Error::other(format!("{err:?}"))
+    }
+    for command in &capture.commands {
+        match command {
+            Command::LoadTexture(id, texture) => {
+                let texture = backend
+                    .load_texture(texture.clone())
+                    .map_err(backend_error)?;
+                *new_slot(&mut textures, *id)? = Some(texture);
+                owned.push(*id);
+            }
+            Command::UnloadTexture(id) => {
+                let texture = textures
+                    .get_mut(*id as usize)
+                    .and_then(Option::take)
+                    .ok_or_else(|| invalid("unknown texture"))?;
+                backend.unload_texture(texture);
+            }
+            Command::UseTexture(id) => {
+                let texture = id
+                    .map(|id| get(&textures, id, "unknown texture"))
+                    .transpose()?;
+                backend.use_texture(texture);
+            }
+            Command::CreateRenderTarget(id, texture_id, viewport) => {
+                let (target, texture) = backend
+                    .create_render_target(*viewport)
+                    .map_err(backend_error)?;
+                *new_slot(&mut render_targets, *id)? = Some(target);
+                *new_slot(&mut textures, *texture_id)? = Some(texture);
+            }
+            Command::SetRenderTarget(id, clear) => {
+                let target = id
+                    .map(|id| get(&render_targets, id, "unknown render target"))
+                    .transpose()?;
+                backend.set_render_target(target, *clear);
+            }
+            Command::SetSampler(sampler) => backend.set_sampler(*sampler),
+            Command::SetDepth(depth) => backend.set_depth(*depth),
+            Command::SetAlpha(alpha) => backend.set_alpha(*alpha),
+            Command::Draw(triangles) => backend.draw(triangles),
+            Command::BeginFrame(clear) => backend.begin_frame(*clear),
+            Command::EndFrame => backend.end_frame(),
+        }
+    }
+    backend.use_texture(None);
+    for id in owned {
+        if let Some(texture) = textures[id as usize].take() {
+            backend.unload_texture(texture);
+        }
+    }
+    Ok(())
+}
+
+struct RecordedTexture<T> {
+    texture: T,
+    // None for render target textures, their contents are produced by the frame
+    data: Option<Texture<'static>>,
+}
+
+struct RecordedRenderTarget<R> {
+    target: R,
+    texture: u32,
+    viewport: Viewport,
+}
+
+// a Backend that passes all calls on to B and captures one frame of them. It keeps a copy
+// of every loaded texture, since the captured frame may use textures loaded long before.
+pub struct Recorder<B: Backend> {
+    inner: B,
+    textures: Vec<Option<RecordedTexture<B::Texture>>>,
+    render_targets: Vec<RecordedRenderTarget<B::RenderTarget>>,
+    texture: Option<u32>,
+    sampler: SamplerState,
+    depth: DepthState,
+    alpha: AlphaState,
+    frame: u64,
+    capture_frame: u64,
+    path: PathBuf,
+    // Some while the capture frame is being recorded
+    commands: Option<Vec<Command>>,
+}
+
+impl<B: Backend> Recorder<B> {
+    // captures the frame with the given number, counting from 0, to path.
+    pub fn new(inner: B, capture_frame: u64, path: impl Into<PathBuf>) -> Self {
+        Recorder {
+            inner,
+            textures: Vec::new(),
+            render_targets: Vec::new(),
+            texture: None,
+            sampler: SamplerState::default(),
+            depth: DepthState::default(),
+            alpha: AlphaState::default(),
+            frame: 0,
+            capture_frame,
+            path: path.into(),
+            commands: None,
+        }
+    }
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+    fn record(&mut self, command: impl FnOnce() -> Command) {
+        if let Some(commands) = &mut self.commands {
+            commands.push(command());
+        }
+    }
+    // commands that recreate everything the frame may depend on
+    fn snapshot(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for (id, t) in self.textures.iter().enumerate() {
+            if let Some(RecordedTexture {
+                data: Some(data), ..
+            }) = t
+            {
+                commands.push(Command::LoadTexture(id as u32, data.clone()));
+            }
+        }
+        for (id, rt) in self.render_targets.iter().enumerate() {
+            commands.push(Command::CreateRenderTarget(
+                id as u32,
+                rt.texture,
+                rt.viewport,
+            ));
+        }
+        commands.extend([
+            Command::UseTexture(self.texture),
+            Command::SetSampler(self.sampler),
+            Command::SetDepth(self.depth),
+            Command::SetAlpha(self.alpha),
+        ]);
+        commands
+    }
+    fn add_texture(&mut self, texture: RecordedTexture<B::Texture>) -> u32 {
+        self.textures.push(Some(texture));
+        (self.textures.len() - 1) as u32
+    }
+}
+
+// lets code that is generic over the backend reach the wrapped one
+impl<B: Backend> Borrow<B> for Recorder<B> {
+    fn borrow(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: Backend> Backend for Recorder<B> {
+    type Texture = u32;
+    type RenderTarget = u32;
+    type Error = B::Error;
+
+    fn viewport(&self) -> Viewport {
+        self.inner.viewport()
+    }
+
+    fn load_texture(&mut self, texture: Texture) -> Result<Self::Texture, Self::Error> {
+        let data = Texture {
+            data: texture.data.clone().into_owned().into(),
+            ty: texture.ty.clone(),
+        };
+        let texture = self.inner.load_texture(texture)?;
+        let id = self.add_texture(RecordedTexture {
+            texture,
+            data: Some(data.clone()),
+        });
+        self.record(|| Command::LoadTexture(id, data));
+        Ok(id)
+    }
+
+    fn unload_texture(&mut self, texture: Self::Texture) {
+        let recorded = self.textures[texture as usize].take().unwrap();
+        self.inner.unload_texture(recorded.texture);
+        self.record(|| Command::UnloadTexture(texture));
+    }
+
+    fn use_texture(&mut self, texture: Option<&Self::Texture>) {
+        self.texture = texture.copied();
+        let inner = texture.map(|&id| &self.textures[id as usize].as_ref().unwrap().texture);
+        self.inner.use_texture(inner);
+        self.record(|| Command::UseTexture(texture.copied()));
+    }
+
+    fn create_render_target(
+        &mut self,
+        viewport: Viewport,
+    ) -> Result<(Self::RenderTarget, Self::Texture), Self::Error> {
+        let (target, texture) = self.inner.create_render_target(viewport)?;
+        let texture = self.add_texture(RecordedTexture {
+            texture,
+            data: None,
+        });
+        self.render_targets.push(RecordedRenderTarget {
+            target,
+            texture,
+            viewport,
+        });
+        let id = (self.render_targets.len() - 1) as u32;
+        self.record(|| Command::CreateRenderTarget(id, texture, viewport));
+        Ok((id, texture))
+    }
+
+    fn set_render_target(&mut self, target: Option<&Self::RenderTarget>, clear: Color) {
+        let inner = target.map(|&id| &self.render_targets[id as usize].target);
+        self.inner.set_render_target(inner, clear);
+        self.record(|| Command::SetRenderTarget(target.copied(), clear));
+    }
+
+    fn set_sampler(&mut self, sampler: SamplerState) {
+        self.sampler = sampler;
+        self.inner.set_sampler(sampler);
+        self.record(|| Command::SetSampler(sampler));
+    }
+
+    fn set_depth(&mut self, depth: DepthState) {
+        self.depth = depth;
+        self.inner.set_depth(depth);
+        self.record(|| Command::SetDepth(depth));
+    }
+
+    fn set_alpha(&mut self, alpha: AlphaState) {
+        self.alpha = alpha;
+        self.inner.set_alpha(alpha);
+        self.record(|| Command::SetAlpha(alpha));
+    }
+
+    fn draw(&mut self, triangles: &[BackendTriangle]) {
+        self.inner.draw(triangles);
+        self.record(|| Command::Draw(triangles.to_vec()));
+    }
+
+    fn begin_frame(&mut self, clear: Color) {
+        if self.frame == self.capture_frame {
+            self.commands = Some(self.snapshot());
+        }
+        self.inner.begin_frame(clear);
+        self.record(|| Command::BeginFrame(clear));
+    }
+
+    fn end_frame(&mut self) {
+        self.inner.end_frame();
+        self.record(|| Command::EndFrame);
+        if let Some(commands) = self.commands.take() {
+            let capture = Capture {
+                viewport: self.inner.viewport(),
+                commands,
+            };
+            match capture.save(&self.path) {
+                Ok(()) => println!("captured frame {} to {}", self.frame, self.path.display()),
+                Err(err) => eprintln!("failed to write {}: {err}", self.path.display()),
+            }
+        }
+        self.frame += 1;
+    }
+
+    fn present(&mut self) {
+        self.inner.present();
+    }
+}
+
+// one line per command and per drawn triangle, for inspecting what a frame submitted
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::LoadTexture(id, texture) => {
+                let TextureType { width, height, .. } = texture.ty;
+                write!(f, "LoadTexture {id} {width}x{height}")
+            }
+            Command::Draw(triangles) => {
+                write!(f, "Draw {} triangles", triangles.len())?;
+                for (i, t) in triangles.iter().enumerate() {
+                    let BBox {
+                        min_x,
+                        max_x,
+                        min_y,
+                        max_y,
+                    } = t.bbox;
+                    write!(
+                        f,
+                        "\n  {i}: bbox {min_x}..={max_x} {min_y}..={max_y} edge_mat {:?} \
+                         uv {:?} rgb {:08x?}",
+                        t.edge_mat, t.uv, t.rgb
+                    )?;
+                }
+                Ok(())
+            }
+            _ => write!(f, "{self:?}"),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// the binary format: little endian integers, f64 as their bits, sizes as u32 and
+// enums as a u8 index.
+trait Encode: Sized {
+    fn encode(&self, w: &mut Vec<u8>);
+    fn decode(r: &mut &[u8]) -> io::Result<Self>;
+}
+
+macro_rules! encode_int {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode(&self, w: &mut Vec<u8>) {
+                w.extend(self.to_le_bytes());
+            }
+            fn decode(r: &mut &[u8]) -> io::Result<Self> {
+                let (bytes, rest) = r
+                    .split_first_chunk()
+                    .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+                *r = rest;
+                Ok(<$t>::from_le_bytes(*bytes))
+            }
+        }
+    )*};
+}
+
+encode_int!(u8, u32, f64);
+
+impl Encode for usize {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (*self as u32).encode(w);
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        Ok(u32::decode(r)? as usize)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (*self as u8).encode(w);
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        Ok(u8::decode(r)? != 0)
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, w: &mut Vec<u8>) {
+        self.iter().for_each(|x| x.encode(w));
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        let v = (0..N)
+            .map(|_| T::decode(r))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(v.try_into().ok().unwrap())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        self.is_some().encode(w);
+        if let Some(x) = self {
+            x.encode(w);
+        }
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        if bool::decode(r)? {
+            Ok(Some(T::decode(r)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        self.len().encode(w);
+        self.iter().for_each(|x| x.encode(w));
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        let len = usize::decode(r)?;
+        (0..len).map(|_| T::decode(r)).collect()
+    }
+}
+
+macro_rules! encode_enum {
+    ($t:ident, $($v:ident),*) => {
+        impl Encode for $t {
+            fn encode(&self, w: &mut Vec<u8>) {
+                (*self as u8).encode(w);
+            }
+            fn decode(r: &mut &[u8]) -> io::Result<Self> {
+                let i = u8::decode(r)? as usize;
+                [$($t::$v),*]
+                    .get(i)
+                    .copied()
+                    .ok_or_else(|| invalid(concat!("invalid ", stringify!($t))))
+            }
+        }
+    };
+}
+
+encode_enum!(WrapMode, Wrap, ClampToEdge, ClampToBorder);
+encode_enum!(DepthMode, Always, Never, Lt, Le, Gt, Ge, Eq, Ne);
+encode_enum!(AlphaMode, Opaque, Mask, Blend);
+
+macro_rules! encode_struct {
+    ($t:ident { $($f:ident),* }) => {
+        impl Encode for $t {
+            fn encode(&self, w: &mut Vec<u8>) {
+                $(self.$f.encode(w);)*
+            }
+            fn decode(r: &mut &[u8]) -> io::Result<Self> {
+                Ok($t { $($f: Encode::decode(r)?),* })
+            }
+        }
+    };
+}
+
+encode_struct!(Color { r, g, b, a });
+encode_struct!(Viewport { width, height });
+encode_struct!(TextureType {
+    width,
+    height,
+    stride
+});
+encode_struct!(SamplerState { wrap, border });
+encode_struct!(DepthState { mode, write });
+encode_struct!(AlphaState { mode, cutoff });
+encode_struct!(BBox {
+    min_x,
+    max_x,
+    min_y,
+    max_y
+});
+encode_struct!(BackendTriangle {
+    edge_mat,
+    uv,
+    rgb,
+    bbox
+});
+
+impl Encode for Texture<'static> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        self.ty.encode(w);
+        self.data.len().encode(w);
+        w.extend_from_slice(&self.data);
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        let ty = TextureType::decode(r)?;
+        let len = usize::decode(r)?;
+        if r.len() < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let (data, rest) = r.split_at(len);
+        *r = rest;
+        Ok(Texture {
+            data: data.to_vec().into(),
+            ty,
+        })
+    }
+}
+
+impl Encode for Command {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            Command::LoadTexture(id, texture) => {
+                0u8.encode(w);
+                id.encode(w);
+                texture.encode(w);
+            }
+            Command::UnloadTexture(id) => {
+                1u8.encode(w);
+                id.encode(w);
+            }
+            Command::UseTexture(id) => {
+                2u8.encode(w);
+                id.encode(w);
+            }
+            Command::CreateRenderTarget(id, texture, viewport) => {
+                3u8.encode(w);
+                id.encode(w);
+                texture.encode(w);
+                viewport.encode(w);
+            }
+            Command::SetRenderTarget(id, clear) => {
+                4u8.encode(w);
+                id.encode(w);
+                clear.encode(w);
+            }
+            Command::SetSampler(sampler) => {
+                5u8.encode(w);
+                sampler.encode(w);
+            }
+            Command::SetDepth(depth) => {
+                6u8.encode(w);
+                depth.encode(w);
+            }
+            Command::SetAlpha(alpha) => {
+                7u8.encode(w);
+                alpha.encode(w);
+            }
+            Command::Draw(triangles) => {
+                8u8.encode(w);
+                triangles.encode(w);
+            }
+            Command::BeginFrame(clear) => {
+                9u8.encode(w);
+                clear.encode(w);
+            }
+            Command::EndFrame => 10u8.encode(w),
+        }
+    }
+    fn decode(r: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::decode(r)? {
+            0 => Command::LoadTexture(u32::decode(r)?, Texture::decode(r)?),
+            1 => Command::UnloadTexture(u32::decode(r)?),
+            2 => Command::UseTexture(Encode::decode(r)?),
+            3 => {
+                Command::CreateRenderTarget(u32::decode(r)?, u32::decode(r)?, Viewport::decode(r)?)
+            }
+            4 => Command::SetRenderTarget(Encode::decode(r)?, Color::decode(r)?),
+            5 => Command::SetSampler(SamplerState::decode(r)?),
+            6 => Command::SetDepth(DepthState::decode(r)?),
+            7 => Command::SetAlpha(AlphaState::decode(r)?),
+            8 => Command::Draw(Encode::decode(r)?),
+            9 => Command::BeginFrame(Color::decode(r)?),
+            10 => Command::EndFrame,
+            _ => return Err(invalid("invalid command")),
+        })
+    }
+}
@@ -0,0 +1,183 @@
+// Bit-accurate version of the hardware pipeline in hw/*.bsv. Everything here works on
+// the integer values the driver hands to the hardware, with the same widths and
+// truncations, so that a software renderer built on it produces identical frames.
+
+use super::{BackendTriangle, SamplerState, TILE_SIZE, Texture, TextureType, WrapMode};
+
+// the layout the hardware DMAs in, see TopLevel.bsv
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct HwTriangle {
+    // per edge: x step, y step and the value at the top left of the bbox, Q7.20
+    pub edge_vec: [[i32; 3]; 3],
+    // Q7.11 with a bias of 2^17, so that negative coordinates stay positive
+    pub uv: [[i32; 2]; 3],
+    pub rgb: [u32; 3],
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl HwTriangle {
+    pub fn new(c: &BackendTriangle) -> Self {
+        let edge_vec = std::array::from_fn(|i| {
+            std::array::from_fn(|j| (c.edge_mat[j][i] * (1 << 20) as f64) as i32)
+        });
+        let uv =
+            c.uv.map(|row| row.map(|n| (n * (1 << 11) as f64) as i32 + (1 << 17)));
+        HwTriangle {
+            edge_vec,
+            uv,
+            // the hardware has no vertex alpha
+            rgb: c.rgb.map(|rgb| rgb & 0xff_ffff),
+            min_x: c.bbox.min_x as u16,
+            min_y: c.bbox.min_y as u16,
+            max_x: c.bbox.max_x as u16,
+            max_y: c.bbox.max_y as u16,
+        }
+    }
+    // Int #(27) edge function values at pixel (x, y), relative to the top left of the bbox.
+    pub fn edge_fns(&self, x: usize, y: usize) -> [i64; 3] {
+        self.edge_vec.map(|e| {
+            let [ex, ey, a] = e.map(|v| sext(v as i64, 27));
+            sext(a + ex * x as i64 + ey * y as i64, 27)
+        })
+    }
+    // CoarseRaster: can any pixel of the tile at (tx, ty), relative to the bbox, be inside?
+    pub fn tile_viable(&self, tx: usize, ty: usize) -> bool {
+        let (x, y) = (tx * TILE_SIZE, ty * TILE_SIZE);
+        let last = TILE_SIZE - 1;
+        let corners = [(0, 0), (last, 0), (0, last), (last, last)]
+            .map(|(cx, cy)| self.edge_fns(x + cx, y + cy));
+        (0..3).all(|i| corners.iter().any(|c| c[i] >= 0))
+    }
+    // UVInterp: perspective correct uv (still biased) and vertex color at a pixel.
+    pub fn interpolate(&self, e: [i64; 3]) -> ([u64; 2], [u8; 3]) {
+        let sum = mask(sext(e[0] + e[1] + e[2], 27) as u64, 27);
+        let (w, shift) = reciprocal(sum);
+        let shift = 26u32.wrapping_sub(shift) & 31;
+        let bary = e.map(|e| mask(mask(mask(e as u64, 27) * w, 54) >> shift, 27));
+        let mut uv = [0; 2];
+        let mut rgb = [0; 3];
+        for ((uv_i, rgb_i), bary) in self.uv.iter().zip(self.rgb).zip(bary) {
+            for (u, &uv_i) in uv.iter_mut().zip(uv_i) {
+                *u = mask(*u + mask(uv_i as u64, 18) * (bary >> 9), 36);
+            }
+            for (c, rgb_i) in rgb.iter_mut().zip((rgb_i & 0xffffff).to_le_bytes()) {
+                *c = mask(*c + rgb_i as u64 * (bary >> 17), 18);
+            }
+        }
+        (uv.map(|u| mask(u >> 18, 18)), rgb.map(|c| (c >> 10) as u8))
+    }
+}
+
+// DepthTest: the Int #(16) depth stored in the depth buffer, larger is closer.
+pub fn depth(e: [i64; 3]) -> i16 {
+    (sext(e[0] + e[1] + e[2], 27) >> 11) as i16
+}
+
+// Reciprocal #(27): returns (value, shift) with value ~ 2^53 / (x << shift)
+// and x << shift normalised to have the top bit set.
+pub fn reciprocal(x: u64) -> (u64, u32) {
+    const N: u32 = 27;
+    // countZerosMSB, 0 gives a shift of N
+    let shift = x.leading_zeros() - (64 - N);
+    let x = mask(x << shift, N);
+    let y0 = mask(x * ((32 << (N - 1)) / 17), 2 * N);
+    let y1 = mask(((48 << (N - 1)) / 17u64).wrapping_sub(y0 >> N), 2 * N);
+    let mut y = mask(y1, N);
+    for _ in 0..4 {
+        let z0 = mask((1u64 << (2 * N - 1)).wrapping_sub(x * y), 2 * N);
+        let z = mask(sext((z0 >> N) as i64, N) as u64, 2 * N);
+        let y0 = mask((y << (N - 1)).wrapping_add(y.wrapping_mul(z)), 2 * N);
+        y = mask(y0 >> (N - 1), N);
+    }
+    (y, shift)
+}
+
+// the texture size fields hold log2(size / 8), so sizes are powers of two from 8 to 1024
+pub fn texture_size_log2(size: usize) -> Option<u32> {
+    (size.is_power_of_two() && (8..=1024).contains(&size)).then(|| size.trailing_zeros() - 3)
+}
+
+// whether the hardware can sample the texture, others have to be rejected when loading
+pub fn supports_texture(ty: &TextureType) -> bool {
+    [ty.width, ty.height, ty.stride]
+        .into_iter()
+        .all(|size| texture_size_log2(size).is_some())
+}
+
+// Texture: returns the texel index along one axis, or None for the border color.
+// Note that the hardware covers 512 texels per uv unit, whatever the texture size.
+fn clamp_or_wrap(u: u64, size: usize, wrap: WrapMode) -> Option<u64> {
+    let u0 = (u >> 2) & 0xffff;
+    let size = texture_size_log2(size).expect("texture not supported by the hardware");
+    let mask = ((0xffff << 3) << size) & 0xffff;
+    let over = (u0 & mask) > 0x8000;
+    let under = (u0 & mask) < 0x8000;
+    let value = match wrap {
+        WrapMode::Wrap => u0 & !mask,
+        WrapMode::ClampToBorder if over || under => return None,
+        _ if over => 0x7fff & !mask,
+        _ if under => 0,
+        _ => u0 & !mask,
+    };
+    Some(value & 0x3ff)
+}
+
+// Texture: the rgb written to the framebuffer for a pixel. Untextured pixels use
+// the vertex color, textured ones modulate the texel with it.
+pub fn shade(
+    uv: [u64; 2],
+    rgb: [u8; 3],
+    texture: Option<&Texture>,
+    sampler: SamplerState,
+) -> [u8; 3] {
+    let Some(texture) = texture else {
+        return rgb;
+    };
+    let u = clamp_or_wrap(uv[0], texture.ty.width, sampler.wrap);
+    let v = clamp_or_wrap(uv[1], texture.ty.height, sampler.wrap);
+    let texel = if let (Some(u), Some(v)) = (u, v) {
+        let addr = (u as usize + v as usize * texture.ty.stride) * 4;
+        [0, 1, 2].map(|i| texture.data[addr + i])
+    } else {
+        [sampler.border.r, sampler.border.g, sampler.border.b]
+    };
+    // FIXME: mirrors the hardware, which divides by 256 rather than 255
+    [0, 1, 2].map(|i| ((texel[i] as u16 * rgb[i] as u16) >> 8) as u8)
+}
+
+// sign extends the low `bits` bits, like assigning to an Int #(bits)
+fn sext(x: i64, bits: u32) -> i64 {
+    (x << (64 - bits)) >> (64 - bits)
+}
+
+fn mask(x: u64, bits: u32) -> u64 {
+    x & ((1 << bits) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_sizes() {
+        for log2 in 0..8 {
+            assert_eq!(texture_size_log2(8 << log2), Some(log2));
+        }
+        for size in [0, 1, 4, 12, 2048] {
+            assert_eq!(texture_size_log2(size), None);
+        }
+        let ty = |width, height, stride| TextureType {
+            width,
+            height,
+            stride,
+        };
+        assert!(supports_texture(&ty(16, 8, 16)));
+        assert!(!supports_texture(&ty(12, 16, 12)));
+        assert!(!supports_texture(&ty(4, 4, 4)));
+        assert!(!supports_texture(&ty(16, 16, 24)));
+    }
+}
@@ -0,0 +1,266 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::AssetLoader,
+    input::{ActionMap, InputSource, InputState},
+    render::{Backend, Context},
+    scene::{self, Scene},
+};
+
+// scenes are always updated in steps of this many seconds, independent of the frame rate.
+pub const TIME_STEP: f64 = 1.0 / 60.0;
+// when rendering falls further behind than this, the lost time is dropped
+// instead of trying to catch up.
+const MAX_STEPS_PER_FRAME: usize = 10;
+
+// everything besides the input that decides how a scene plays out. Input recordings store it,
+// so that they are replayed in the same conditions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub scene: String,
+    // for the randomness of the scene
+    pub seed: u64,
+    pub mouse_sensitivity: f64,
+    pub bindings: ActionMap,
+}
+
+impl Session {
+    pub fn new(scene: &str, seed: u64) -> Self {
+        Session {
+            scene: scene.to_string(),
+            seed,
+            mouse_sensitivity: 1.0,
+            bindings: ActionMap::default(),
+        }
+    }
+}
+
+// for sessions that should play out differently each time
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+pub struct Runner<B: Backend> {
+    context: Context<B>,
+    scene: Box<dyn Scene<B>>,
+    input_source: Box<dyn InputSource>,
+    input_state: InputState,
+    // the input as of the end of the last tick
+    previous_input: InputState,
+    action_map: ActionMap,
+    // the number of updates so far
+    tick: u64,
+}
+
+impl<B: Backend> Runner<B> {
+    // returns None if the scene spec doesn't name a known scene.
+    pub fn new(backend: B, input_source: impl InputSource + 'static, scene: &str) -> Option<Self> {
+        Self::with_session(backend, input_source, &Session::new(scene, 0))
+    }
+    pub fn with_session(
+        backend: B,
+        input_source: impl InputSource + 'static,
+        session: &Session,
+    ) -> Option<Self> {
+        Self::with_assets(backend, input_source, session, &mut AssetLoader::default())
+    }
+    // loads the assets of the scene with loader instead of from the working directory
+    pub fn with_assets(
+        backend: B,
+        input_source: impl InputSource + 'static,
+        session: &Session,
+        loader: &mut AssetLoader,
+    ) -> Option<Self> {
+        let mut context = Context::new(backend);
+        let scene = scene::create(&session.scene, &mut context, loader, session.seed)?;
+        let mut input_state = InputState::default();
+        input_state.set_mouse_sensitivity(session.mouse_sensitivity);
+        Some(Runner {
+            context,
+            scene,
+            input_source: Box::new(input_source),
+            previous_input: input_state.clone(),
+            input_state,
+            action_map: session.bindings.clone(),
+            tick: 0,
+        })
+    }
+    pub fn context(&self) -> &Context<B> {
+        &self.context
+    }
+    pub fn context_mut(&mut self) -> &mut Context<B> {
+        &mut self.context
+    }
+    pub fn action_map(&self) -> &ActionMap {
+        &self.action_map
+    }
+    // bindings can be changed between frames, e.g. to load them from a file
+    pub fn action_map_mut(&mut self) -> &mut ActionMap {
+        &mut self.action_map
+    }
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f64) {
+        self.input_state.set_mouse_sensitivity(sensitivity);
+    }
+    fn poll_input(&mut self) {
+        self.input_source.set_tick(self.tick);
+        while let Some(event) = self.input_source.poll_event() {
+            self.input_state.update(event.clone());
+            self.scene.input(event);
+        }
+    }
+    // presses and mouse motion are seen by exactly one update, even if a frame runs several
+    // or none. Input is polled before each update, so that every event is seen by the same
+    // update however the updates are spread over frames.
+    fn update(&mut self) {
+        self.poll_input();
+        let actions = self
+            .action_map
+            .actions(&self.input_state, &self.previous_input);
+        self.scene.update(TIME_STEP, &actions);
+        self.input_state.next_tick();
+        self.previous_input.clone_from(&self.input_state);
+        self.tick += 1;
+    }
+    fn render(&mut self) {
+        self.context.begin_frame(self.scene.clear_color());
+        self.scene.render(&mut self.context);
+        self.context.end_frame();
+        self.context.present();
+    }
+    // runs until quit returns true, it is checked once per frame after input has been polled.
+    pub fn run(mut self, mut quit: impl FnMut(&B, &InputState) -> bool) {
+        let mut last = Instant::now();
+        let mut lag = 0.0;
+        loop {
+            self.poll_input();
+            if quit(self.context.backend(), &self.input_state) {
+                break;
+            }
+            let now = Instant::now();
+            lag += (now - last).as_secs_f64();
+            last = now;
+            let mut steps = 0;
+            while lag >= TIME_STEP {
+                if steps == MAX_STEPS_PER_FRAME {
+                    lag = 0.0;
+                    break;
+                }
+                lag -= TIME_STEP;
+                steps += 1;
+            }
+            self.frame(steps);
+        }
+    }
+    fn frame(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.update();
+        }
+        self.render();
+    }
+    // deterministic variant of run for offline rendering: every frame advances the scene by
+    // exactly one TIME_STEP, regardless of how long rendering takes.
+    pub fn run_frames(mut self, frames: usize, mut frame_done: impl FnMut(&B, usize)) {
+        for i in 0..frames {
+            self.update();
+            self.render();
+            frame_done(self.context.backend(), i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use super::*;
+    use crate::{
+        input::{Actions, InputEvent, Key},
+        mesh::Color,
+        render::{AlphaState, BackendTriangle, DepthState, SamplerState, Texture, Viewport},
+    };
+
+    struct NullBackend;
+
+    impl Backend for NullBackend {
+        type Texture = ();
+        type RenderTarget = ();
+        type Error = ();
+        fn viewport(&self) -> Viewport {
+            Viewport::new(320, 240)
+        }
+        fn load_texture(&mut self, _: Texture) -> Result<(), ()> {
+            Ok(())
+        }
+        fn unload_texture(&mut self, _: ()) {}
+        fn use_texture(&mut self, _: Option<&()>) {}
+        fn create_render_target(&mut self, _: Viewport) -> Result<((), ()), ()> {
+            Ok(((), ()))
+        }
+        fn set_render_target(&mut self, _: Option<&()>, _: Color) {}
+        fn set_sampler(&mut self, _: SamplerState) {}
+        fn set_depth(&mut self, _: DepthState) {}
+        fn set_alpha(&mut self, _: AlphaState) {}
+        fn draw(&mut self, _: &[BackendTriangle]) {}
+        fn begin_frame(&mut self, _: Color) {}
+        fn end_frame(&mut self) {}
+        fn present(&mut self) {}
+    }
+
+    // counts the updates that see Space pressed
+    struct PressCounter(Rc<RefCell<usize>>);
+
+    impl Scene<NullBackend> for PressCounter {
+        fn render(&mut self, _: &mut Context<NullBackend>) {}
+        fn update(&mut self, _: f64, input: &Actions) {
+            *self.0.borrow_mut() += input.was_key_pressed(Key::Space) as usize;
+        }
+    }
+
+    struct Events(Rc<RefCell<VecDeque<InputEvent>>>);
+
+    impl InputSource for Events {
+        fn poll_event(&mut self) -> Option<InputEvent> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    #[test]
+    fn press_is_seen_by_one_update() {
+        let presses = Rc::new(RefCell::new(0));
+        let events = Rc::new(RefCell::new(VecDeque::new()));
+        let mut runner = Runner {
+            context: Context::new(NullBackend),
+            scene: Box::new(PressCounter(presses.clone())),
+            input_source: Box::new(Events(events.clone())),
+            input_state: InputState::default(),
+            previous_input: InputState::default(),
+            action_map: ActionMap::default(),
+            tick: 0,
+        };
+        // pressed during a frame without updates, and held through the next ones
+        events
+            .borrow_mut()
+            .push_back(InputEvent::KeyDown(Key::Space));
+        runner.poll_input();
+        runner.frame(0);
+        runner.poll_input();
+        runner.frame(3);
+        assert_eq!(*presses.borrow(), 1);
+        events.borrow_mut().push_back(InputEvent::KeyUp(Key::Space));
+        runner.poll_input();
+        runner.frame(1);
+        // tapped during a frame without updates, before a frame with several
+        events.borrow_mut().extend([
+            InputEvent::KeyDown(Key::Space),
+            InputEvent::KeyUp(Key::Space),
+        ]);
+        runner.poll_input();
+        runner.frame(0);
+        runner.poll_input();
+        runner.frame(4);
+        assert_eq!(*presses.borrow(), 2);
+        assert_eq!(runner.tick, 8);
+    }
+}
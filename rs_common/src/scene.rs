@@ -8,9 +8,9 @@ use crate::{
     entity::{Camera, EntityId, Transform, World},
     geometry::{Matrix, Quaternion, Vec2, Vec3},
     gltf::GltfImporter,
-    input::{InputEvent, InputState, Key},
+    input::{Actions, InputEvent},
     mesh::{Color, Material, Mesh, Texture},
-    render::{Backend, Context, HEIGHT, TextureId, Triangle4, WIDTH},
+    render::{Attributes, Backend, Context, Triangle4},
     *,
 };
 
@@ -20,14 +20,18 @@ mod tetris;
 #[allow(unused_variables)]
 pub trait Scene<B: Backend> {
     fn input(&mut self, event: InputEvent) {}
+    fn clear_color(&self) -> Color {
+        Color::BLACK
+    }
     fn render(&mut self, context: &mut Context<B>);
-    fn update(&mut self, delta: f64, input: &InputState) {}
+    fn update(&mut self, delta: f64, input: &Actions) {}
 }
 
 pub fn create<B: Backend>(
     spec: &str,
     context: &mut Context<B>,
     loader: &mut AssetLoader,
+    seed: u64,
 ) -> Option<Box<dyn Scene<B>>> {
     let (name, arg) = match spec.split_once(':') {
         Some((a, b)) => (a, Some(b)),
@@ -38,7 +42,7 @@ pub fn create<B: Backend>(
         ("CatRoom", None) => Some(Box::new(CatRoom::new(context, loader))),
         ("Gltf", Some(path)) => Some(Box::new(GltfScene::new(context, loader, path))),
         ("Sphere", None) => Some(Box::new(Sphere::default())),
-        ("Tetris", None) => Some(Box::new(tetris::Tetris::new(context, loader))),
+        ("Tetris", None) => Some(Box::new(tetris::Tetris::new(context, loader, seed))),
         _ => None,
     }
 }
@@ -107,7 +111,7 @@ pub const CUBE: &'static [[[f64; 5]; 3]] = &[
 ];
 
 fn cube_mesh() -> Rc<Mesh> {
-    let material = Rc::new(Material { texture: None });
+    let material = Rc::new(Material::default());
     Rc::new(Mesh {
         vertices: [1.0, -1.0]
             .into_iter()
@@ -134,11 +138,13 @@ fn cube_mesh() -> Rc<Mesh> {
             [4, 7, 6],
         ],
         material_ranges: vec![(material, 0..12)],
+        buffer: Default::default(),
     })
 }
 
 pub struct Cube {
-    texture: TextureId,
+    // keeps the texture loaded, it is freed once the last Rc is dropped
+    texture: Rc<Texture>,
     time: f64,
 }
 
@@ -146,14 +152,16 @@ impl Cube {
     fn new<B: Backend>(context: &mut Context<B>, loader: &mut AssetLoader) -> Cube {
         let image = Texture::from_file("cat", None);
         image.load_backend(context, loader);
-        let texture = image.texture_id();
-        Cube { texture, time: 0.0 }
+        Cube {
+            texture: image,
+            time: 0.0,
+        }
     }
 }
 
 impl<B: Backend> Scene<B> for Cube {
     fn render(&mut self, context: &mut Context<B>) {
-        let matrix = Matrix::projection(90.0, WIDTH as f64, HEIGHT as f64, 0.1, 100.0)
+        let matrix = context.viewport().projection(90.0, 0.1, 100.0)
             * Matrix::translate(0.0, -0.0, 3.0)
             * Matrix::rotate(30.0 * self.time, [0.0, 1.0, 0.0]);
         let v: Vec<_> = CUBE
@@ -161,15 +169,16 @@ impl<B: Backend> Scene<B> for Cube {
             .map(|v| Triangle4::new(*v))
             .map(move |p| p.transform(matrix))
             .collect();
-        context.draw().textured(self.texture).run(&v);
+        context.draw().textured(self.texture.texture_id()).run(&v);
     }
-    fn update(&mut self, delta: f64, _input: &InputState) {
+    fn update(&mut self, delta: f64, _input: &Actions) {
         self.time += delta;
     }
 }
 
 pub struct CatRoom {
-    texture: TextureId,
+    // keeps the texture loaded, it is freed once the last Rc is dropped
+    texture: Rc<Texture>,
     time: f64,
 }
 
@@ -177,14 +186,16 @@ impl CatRoom {
     fn new<B: Backend>(context: &mut Context<B>, loader: &mut AssetLoader) -> CatRoom {
         let image = Texture::from_file("cat", None);
         image.load_backend(context, loader);
-        let texture = image.texture_id();
-        CatRoom { texture, time: 0.0 }
+        CatRoom {
+            texture: image,
+            time: 0.0,
+        }
     }
 }
 
 impl<B: Backend> Scene<B> for CatRoom {
     fn render(&mut self, context: &mut Context<B>) {
-        let matrix = Matrix::projection(90.0, WIDTH as f64, HEIGHT as f64, 0.1, 100.0)
+        let matrix = context.viewport().projection(90.0, 0.1, 100.0)
             * Matrix::translate(0.0, -1.0, 3.0)
             * Matrix::rotate(-20.0, [1.0, 0.0, 0.0])
             * Matrix::rotate(30.0 * self.time, [0.0, 1.0, 0.0]);
@@ -193,9 +204,9 @@ impl<B: Backend> Scene<B> for CatRoom {
             .map(|v| Triangle4::new(*v))
             .map(move |p| p.transform(matrix))
             .collect();
-        context.draw().textured(self.texture).run(&v);
+        context.draw().textured(self.texture.texture_id()).run(&v);
     }
-    fn update(&mut self, delta: f64, _input: &InputState) {
+    fn update(&mut self, delta: f64, _input: &Actions) {
         self.time += delta;
     }
 }
@@ -205,9 +216,14 @@ pub struct GltfScene {
     player: EntityId,
     camera_pivot: EntityId,
     camera: EntityId,
+    // the camera rotation, turned with the mouse and the look action
+    rotation: [f64; 2],
     time: f64,
 }
 
+// radians per second with the look action fully tilted
+const STICK_TURN_SPEED: f64 = 2.5;
+
 fn randomize_mesh_colors(mesh: &Mesh) -> Mesh {
     let mut out_mesh = Mesh::default();
     let mut rng = rand::rng();
@@ -280,6 +296,7 @@ impl GltfScene {
             player,
             camera,
             camera_pivot,
+            rotation: [0.0; 2],
             time: 0.0,
         }
     }
@@ -310,29 +327,22 @@ impl<B: Backend> Scene<B> for GltfScene {
             context,
             self.world
                 .get::<Camera>(self.camera)
-                .view_matrix(self.world.get(self.camera)),
+                .view_matrix(self.world.get(self.camera), context.viewport()),
         );
         self.world.render(context, self.camera);
     }
-    fn update(&mut self, delta: f64, input: &InputState) {
-        let rot_x = (input.mouse_x() as f64) / 10.0;
-        let rot_y = (input.mouse_y() as f64) / 10.0;
-        let input_vector: Vec2 = [
-            (input.is_key_down(Key::KeyD) as u32 as f64)
-                - (input.is_key_down(Key::KeyA) as u32 as f64),
-            (input.is_key_down(Key::KeyW) as u32 as f64)
-                - (input.is_key_down(Key::KeyS) as u32 as f64),
-        ]
-        .into();
+    fn update(&mut self, delta: f64, input: &Actions) {
+        let [mouse_x, mouse_y] = input.mouse_delta();
+        let [look_x, look_y] = input.axis2("look");
+        self.rotation[0] += mouse_x / 10.0 + look_x * STICK_TURN_SPEED * delta;
+        self.rotation[1] += mouse_y / 10.0 + look_y * STICK_TURN_SPEED * delta;
+        let [rot_x, rot_y] = self.rotation;
+        let input_vector: Vec2 = input.axis2("move").into();
         let delta_position = input_vector.rotate(-rot_x);
         let Vec3 { x, y, z } = self.world.get::<Transform>(self.player).local_position;
         let mut new_x = x + delta_position.x * delta * 10.0;
         let mut new_z = z + delta_position.y * delta * 10.0;
-        let mut new_y = y
-            + ((input.is_key_down(Key::KeyE) as u32 as f64)
-                - (input.is_key_down(Key::KeyQ) as u32 as f64))
-                * delta
-                * 10.0;
+        let mut new_y = y + input.axis("rise") * delta * 10.0;
 
         let collider = CapsuleCollider {
             base: [0.0, -1.0, 0.0].into(),
@@ -361,8 +371,8 @@ pub struct Sphere {
 
 impl<B: Backend> Scene<B> for Sphere {
     fn render(&mut self, context: &mut Context<B>) {
-        let view = Matrix::projection(90.0, WIDTH as f64, HEIGHT as f64, 0.1, 100.0)
-            * Matrix::translate(0.0, 0.0, 3.0);
+        let view =
+            context.viewport().projection(90.0, 0.1, 100.0) * Matrix::translate(0.0, 0.0, 3.0);
         let theta_steps = 16;
         let phi_steps = 16;
         let coord = |i: i32, j: i32| {
@@ -378,13 +388,11 @@ impl<B: Backend> Scene<B> for Sphere {
             for j in 0..phi_steps {
                 tris.push(Triangle4 {
                     vertices: [coord(i, j), coord(i + 1, j), coord(i + 1, j + 1)],
-                    uv: [Vec2::default(); 3],
-                    color: [Color::WHITE; 3],
+                    attributes: [Attributes::default(); 3],
                 });
                 tris.push(Triangle4 {
                     vertices: [coord(i, j), coord(i + 1, j + 1), coord(i, j + 1)],
-                    uv: [Vec2::default(); 3],
-                    color: [Color::WHITE; 3],
+                    attributes: [Attributes::default(); 3],
                 });
             }
         }
@@ -395,13 +403,13 @@ impl<B: Backend> Scene<B> for Sphere {
                 let direction: Vec3 = [tt.cos(), 0.0, -tt.sin()].into();
                 let s = (normal * direction).clamp(0.0, 1.0);
                 let l = 0.3 + 0.3 * s + 0.3 * s * s * s;
-                t.color[i] = [l, l, l].into();
+                t.attributes[i].color = [l, l, l].into();
             }
         }
         let v: Vec<_> = tris.iter().map(|p| p.transform(view)).collect();
         context.draw().run(&v);
     }
-    fn update(&mut self, delta: f64, _input: &InputState) {
+    fn update(&mut self, delta: f64, _input: &Actions) {
         self.time += delta;
     }
 }
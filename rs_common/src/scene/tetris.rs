@@ -1,46 +1,30 @@
-use rand::{Rng, rng, rngs::ThreadRng};
+use rand::{Rng, SeedableRng, rngs::StdRng};
 
 use crate::{
     assets::AssetLoader,
     geometry::Matrix,
-    input::InputState,
+    input::Actions,
     mesh::Color,
-    render::{Backend, Context, Triangle4, HEIGHT, WIDTH},
+    render::{Backend, Context, Triangle4},
     scene::{Scene, CUBE},
 };
 
 type Block = u8;
 const FALL_TIME: f64 = 0.25;
+// how much faster pieces fall while down is held
+const SOFT_DROP: f64 = 4.0;
+// how far the move action has to be pushed to move a piece
+const MOVE_THRESHOLD: f64 = 0.5;
 
 const COLORS: [Color; 8] = [
-    Color { r: 255, g: 0, b: 0 },
-    Color { r: 0, g: 255, b: 0 },
-    Color { r: 0, g: 0, b: 255 },
-    Color {
-        r: 255,
-        g: 255,
-        b: 0,
-    },
-    Color {
-        r: 0,
-        g: 255,
-        b: 255,
-    },
-    Color {
-        r: 255,
-        g: 0,
-        b: 255,
-    },
-    Color {
-        r: 255,
-        g: 128,
-        b: 128,
-    },
-    Color {
-        r: 128,
-        g: 255,
-        b: 128,
-    },
+    Color::rgb(255, 0, 0),
+    Color::rgb(0, 255, 0),
+    Color::rgb(0, 0, 255),
+    Color::rgb(255, 255, 0),
+    Color::rgb(0, 255, 255),
+    Color::rgb(255, 0, 255),
+    Color::rgb(255, 128, 128),
+    Color::rgb(128, 255, 128),
 ];
 
 const PIECES: [[[u8; 2]; 4]; 7] = [
@@ -76,11 +60,17 @@ pub struct Tetris {
     piece_x: usize,
     piece_y: usize,
     piece_moving: bool,
-    rng: ThreadRng,
+    // the side the move action was pushed to in the last update, -1, 0 or 1
+    move_x: i32,
+    rng: StdRng,
 }
 
 impl Tetris {
-    pub fn new<B: Backend>(_context: &mut Context<B>, _loader: &mut AssetLoader) -> Self {
+    pub fn new<B: Backend>(
+        _context: &mut Context<B>,
+        _loader: &mut AssetLoader,
+        seed: u64,
+    ) -> Self {
         Tetris {
             field: Field::empty(8, 13),
             active_piece: 0,
@@ -88,7 +78,8 @@ impl Tetris {
             piece_x: 0,
             piece_y: 0,
             piece_moving: true,
-            rng: rng(),
+            move_x: 0,
+            rng: StdRng::seed_from_u64(seed),
         }
     }
     fn draw_cube<B: Backend>(
@@ -108,7 +99,9 @@ impl Tetris {
             .iter()
             .map(|x| {
                 let mut t = Triangle4::new(*x).transform(object);
-                t.color = [COLORS[col as usize]; 3];
+                for a in &mut t.attributes {
+                    a.color = COLORS[col as usize];
+                }
                 t.lighting(0.5, 0.5, [0.0, 0.0, 1.0].into()).transform(view)
             })
             .collect::<Vec<_>>();
@@ -118,7 +111,10 @@ impl Tetris {
         for [dx, dy] in PIECES[self.active_piece as usize] {
             let x = (dx as usize) + px;
             let y = (dy as usize) + py;
-            if y >= self.field.height || self.field.blocks[y * self.field.width + x] != 0 {
+            if x >= self.field.width
+                || y >= self.field.height
+                || self.field.blocks[y * self.field.width + x] != 0
+            {
                 return true;
             }
         }
@@ -131,12 +127,21 @@ impl Tetris {
             self.field.blocks[y * self.field.width + x] = self.active_piece + 1;
         }
     }
+    fn move_piece(&mut self, dx: i32) {
+        let Some(x) = self.piece_x.checked_add_signed(dx as isize) else {
+            return;
+        };
+        if !self.intersection_check(x, self.piece_y) {
+            self.piece_x = x;
+            self.piece_moving = !self.intersection_check(self.piece_x, self.piece_y + 1);
+        }
+    }
 }
 
 impl<B: Backend> Scene<B> for Tetris {
     fn render(&mut self, context: &mut crate::render::Context<B>) {
-        let view = Matrix::projection(90.0, WIDTH as f64, HEIGHT as f64, 0.1, 100.0)
-            * Matrix::translate(0.0, -0.0, 5.0);
+        let view =
+            context.viewport().projection(90.0, 0.1, 100.0) * Matrix::translate(0.0, -0.0, 5.0);
         for y in 0..self.field.height {
             for x in 0..self.field.width {
                 let col = self.field.blocks[y * self.field.width + x];
@@ -160,8 +165,16 @@ impl<B: Backend> Scene<B> for Tetris {
             );
         }
     }
-    fn update(&mut self, delta: f64, _input: &InputState) {
-        self.timer += delta;
+    fn update(&mut self, delta: f64, input: &Actions) {
+        // the piece moves once each time move is pushed to a side
+        let [x, y] = input.axis2("move");
+        let move_x = (x.abs() > MOVE_THRESHOLD) as i32 * x.signum() as i32;
+        if move_x != 0 && move_x != self.move_x {
+            self.move_piece(move_x);
+        }
+        self.move_x = move_x;
+        let soft_drop = y < -MOVE_THRESHOLD;
+        self.timer += if soft_drop { delta * SOFT_DROP } else { delta };
         if self.timer >= FALL_TIME {
             if self.intersection_check(self.piece_x, self.piece_y + 1) {
                 self.set_piece();